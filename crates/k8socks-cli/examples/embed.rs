@@ -0,0 +1,43 @@
+//! Runs a proxy session from another program, with the settings of the
+//! k8socks config file and a free local port, until Ctrl+C.
+//!
+//! ```sh
+//! cargo run -p k8socks-cli --example embed -- [NAMESPACE]
+//! ```
+
+use merge::Merge;
+
+use k8socks_cli::{run_session, SessionEnd};
+use k8socks_config::ConfigServiceImpl;
+use k8socks_logging::LoggingServiceImpl;
+use k8socks_traits::config::{Config, ConfigService};
+use k8socks_traits::logging::LoggingService;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    LoggingServiceImpl::init_logging("info", true)
+        .map_err(|e| anyhow::anyhow!("Failed to initialize logging: {}", e))?;
+
+    let mut config = Config::default();
+    config.merge(ConfigServiceImpl::load_from_paths()?);
+    if let Some(namespace) = std::env::args().nth(1) {
+        config.namespace = Some(namespace);
+    }
+    // Let the OS pick the port, so this runs next to a `k8socks deploy`.
+    config.local_socks_port = Some(0);
+
+    let session = run_session(config).await?;
+    println!("export ALL_PROXY=socks5h://127.0.0.1:{}", session.socks_port());
+
+    let trigger = session.shutdown_trigger();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            trigger.shutdown();
+        }
+    });
+    let end = session.wait().await?;
+    if end != SessionEnd::Shutdown {
+        println!("The session ended by itself: {:?}", end);
+    }
+    Ok(())
+}
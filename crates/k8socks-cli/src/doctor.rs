@@ -0,0 +1,88 @@
+use serde::Serialize;
+use tokio::process::Command;
+
+use k8socks_traits::config::Config;
+use k8socks_traits::k8s::K8sService;
+
+use k8socks_k8s::{read_public_keys, ssh_public_key_paths, K8sServiceImpl};
+
+/// The outcome of a single environment check.
+#[derive(Serialize, Debug)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub passed: bool,
+    /// Hard checks must pass for a deploy to work; soft ones are advisory.
+    pub hard: bool,
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, hard: bool, result: Result<String, String>) -> Self {
+        let (passed, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        Self {
+            name,
+            passed,
+            hard,
+            detail,
+        }
+    }
+}
+
+async fn check_ssh_binary(config: &Config) -> CheckResult {
+    let ssh_binary = config.ssh_binary_path.as_deref().unwrap_or("ssh");
+    // `ssh -V` prints its version to stderr
+    let result = match Command::new(ssh_binary).arg("-V").output().await {
+        Ok(output) => Ok(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+        Err(e) => Err(format!("'{}' could not be run: {}", ssh_binary, e)),
+    };
+    CheckResult::new("ssh_binary", true, result)
+}
+
+fn check_ssh_public_key(config: &Config) -> CheckResult {
+    let source = match config.ssh_use_agent_key {
+        Some(true) => "ssh-agent".to_string(),
+        _ => ssh_public_key_paths(config).join(", "),
+    };
+    let result = read_public_keys(config)
+        .map(|_| source)
+        .map_err(|e| e.to_string());
+    CheckResult::new("ssh_public_key", true, result)
+}
+
+async fn check_cluster(config: &Config) -> CheckResult {
+    let result = match K8sServiceImpl::new(config).await {
+        Ok(k8s_service) => match k8s_service.list_pods(false).await {
+            Ok(pods) => Ok(format!("reachable, {} k8socks pod(s) running", pods.len())),
+            Err(e) => Err(e.to_string()),
+        },
+        Err(e) => Err(e.to_string()),
+    };
+    CheckResult::new("cluster", true, result)
+}
+
+/// Runs every check in order.
+pub async fn run_checks(config: &Config) -> Vec<CheckResult> {
+    vec![
+        check_ssh_binary(config).await,
+        check_ssh_public_key(config),
+        check_cluster(config).await,
+    ]
+}
+
+pub fn print_table(results: &[CheckResult]) {
+    for result in results {
+        let status = match (result.passed, result.hard) {
+            (true, _) => "OK",
+            (false, true) => "FAIL",
+            (false, false) => "WARN",
+        };
+        println!("{:<5} {:<16} {}", status, result.name, result.detail);
+    }
+}
+
+pub fn all_hard_checks_passed(results: &[CheckResult]) -> bool {
+    results.iter().all(|r| r.passed || !r.hard)
+}
@@ -0,0 +1,67 @@
+//! The process exit codes of k8socks, so that scripts can tell failures apart.
+//! Keep the table in the README in sync.
+
+use k8socks_traits::config::ConfigError;
+use k8socks_traits::k8s::K8sError;
+use k8socks_traits::ssh::SshError;
+
+/// Any failure without a more specific code.
+pub const GENERIC: u8 = 1;
+/// The configuration, a flag or an SSH key is invalid. Also used by clap for
+/// unknown or malformed flags.
+pub const CONFIG: u8 = 2;
+/// The cluster could not be reached, or the kubeconfig to reach it is missing.
+pub const CLUSTER_UNREACHABLE: u8 = 3;
+/// The pod could not be created, did not become ready or went away.
+pub const POD_FAILED: u8 = 4;
+/// The SSH connection to the pod could not be set up or was lost.
+pub const SSH_UNAVAILABLE: u8 = 5;
+/// Interrupted by Ctrl+C (128 + SIGINT), e.g. during `test`. A running
+/// session stopped with Ctrl+C ends normally with 0.
+pub const INTERRUPTED: u8 = 130;
+
+/// The error for an operation cut short by Ctrl+C.
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// The exit code for an error: that of the first error in its chain of
+/// causes that has a specific code, `GENERIC` otherwise.
+pub fn for_error(err: &anyhow::Error) -> u8 {
+    err.chain()
+        .find_map(|cause| {
+            if cause.is::<ConfigError>() {
+                return Some(CONFIG);
+            }
+            if cause.is::<Interrupted>() {
+                return Some(INTERRUPTED);
+            }
+            if let Some(e) = cause.downcast_ref::<K8sError>() {
+                return k8s_code(e);
+            }
+            cause.downcast_ref::<SshError>().map(|_| SSH_UNAVAILABLE)
+        })
+        .unwrap_or(GENERIC)
+}
+
+fn k8s_code(err: &K8sError) -> Option<u8> {
+    match err {
+        K8sError::Connection { .. } | K8sError::KubeConfig(_) | K8sError::InferConfig(_) => Some(CLUSTER_UNREACHABLE),
+        K8sError::SshKeyError(..)
+        | K8sError::InvalidSshKey(..)
+        | K8sError::CaCert(..)
+        | K8sError::InvalidPodSpecPatch(_)
+        | K8sError::NamespaceNotFound(_)
+        | K8sError::NoNamespace => Some(CONFIG),
+        K8sError::PodNotReady | K8sError::PodNotFound(_) | K8sError::QuotaExceeded => Some(POD_FAILED),
+        K8sError::PortForwardFailed(_) | K8sError::SshdUnreachable(_) => Some(SSH_UNAVAILABLE),
+        K8sError::Kube(_) | K8sError::PodNotDeleted(_) | K8sError::Forbidden { .. } => None,
+    }
+}
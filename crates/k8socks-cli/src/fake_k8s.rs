@@ -0,0 +1,104 @@
+//! An in-memory `K8sService` for the unit tests of the library and the binary.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::{Event, Pod};
+use tokio::net::TcpListener;
+
+use k8socks_traits::config::Config;
+use k8socks_traits::k8s::{K8sError, K8sService, PodRef, PortForwardHandle, QuotaProblem};
+
+/// Creates pods that run nothing: its port-forwards accept connections and
+/// close them right away, as when sshd in the pod never comes up.
+#[derive(Clone, Default)]
+pub struct FakeK8sService {
+    /// Makes `wait_for_pod_ready` fail with `PodNotReady`.
+    pub never_ready: bool,
+    /// The pods created and not deleted since.
+    pub pods: Arc<Mutex<Vec<String>>>,
+    /// The pods `delete_pod` was called for, in order.
+    pub deleted: Arc<Mutex<Vec<String>>>,
+}
+
+fn pod_ref(name: &str) -> PodRef {
+    PodRef {
+        name: name.to_string(),
+        namespace: "default".to_string(),
+        creation_timestamp: None,
+        phase: Some("Running".to_string()),
+        node_name: None,
+    }
+}
+
+#[async_trait]
+impl K8sService for FakeK8sService {
+    async fn new(_config: &Config) -> Result<Self, K8sError> {
+        Ok(Self::default())
+    }
+
+    async fn deploy_pod(&self) -> Result<PodRef, K8sError> {
+        let mut pods = self.pods.lock().unwrap();
+        let name = format!("k8socks-fake{}", pods.len());
+        pods.push(name.clone());
+        Ok(pod_ref(&name))
+    }
+
+    async fn wait_for_pod_ready(&self, _pod_ref: &PodRef) -> Result<Pod, K8sError> {
+        if self.never_ready {
+            return Err(K8sError::PodNotReady);
+        }
+        Ok(Pod::default())
+    }
+
+    async fn port_forward(&self, _pod_ref: &PodRef, local_port: u16) -> Result<PortForwardHandle, K8sError> {
+        let listener = TcpListener::bind(("127.0.0.1", local_port)).await?;
+        let local_port = listener.local_addr()?.port();
+        let handle = tokio::spawn(async move {
+            while let Ok((conn, _)) = listener.accept().await {
+                drop(conn);
+            }
+        });
+        Ok(PortForwardHandle::new(local_port, Default::default(), handle))
+    }
+
+    async fn delete_pod(&self, pod_ref: &PodRef, _grace_period_seconds: Option<u32>) -> Result<(), K8sError> {
+        self.pods.lock().unwrap().retain(|name| *name != pod_ref.name);
+        self.deleted.lock().unwrap().push(pod_ref.name.clone());
+        Ok(())
+    }
+
+    async fn get_pod(&self, pod_ref: &PodRef) -> Result<Option<PodRef>, K8sError> {
+        let pods = self.pods.lock().unwrap();
+        Ok(pods.contains(&pod_ref.name).then(|| pod_ref.clone()))
+    }
+
+    async fn get_pod_node(&self, _pod_ref: &PodRef) -> Result<Option<String>, K8sError> {
+        Ok(None)
+    }
+
+    async fn wait_for_pod_deleted(&self, _pod_ref: &PodRef, _timeout: Duration) -> Result<(), K8sError> {
+        Ok(())
+    }
+
+    async fn list_pods(&self, _all_namespaces: bool) -> Result<Vec<PodRef>, K8sError> {
+        Ok(self.pods.lock().unwrap().iter().map(|name| pod_ref(name)).collect())
+    }
+
+    async fn exec(&self, _pod_ref: &PodRef, _command: Vec<String>, _tty: bool) -> Result<(), K8sError> {
+        unimplemented!("the fake runs no containers")
+    }
+
+    async fn get_pod_events(&self, _pod_ref: &PodRef) -> Result<Vec<Event>, K8sError> {
+        Ok(Vec::new())
+    }
+
+    async fn pod_logs(&self, _pod_ref: &PodRef, _since: Option<Duration>, _tail: Option<u64>) -> Result<String, K8sError> {
+        Ok(String::new())
+    }
+
+    async fn check_quota(&self) -> Result<Vec<QuotaProblem>, K8sError> {
+        Ok(Vec::new())
+    }
+}
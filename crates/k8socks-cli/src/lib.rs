@@ -0,0 +1,10 @@
+//! k8socks as a library, for programs that embed a proxy session instead of
+//! running the `k8socks` binary. See `examples/embed.rs`.
+
+pub mod session;
+pub mod timings;
+
+#[cfg(test)]
+mod fake_k8s;
+
+pub use session::{run_session, run_session_with, SessionEnd, SessionHandle, SessionOptions, ShutdownTrigger};
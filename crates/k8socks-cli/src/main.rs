@@ -1,19 +1,45 @@
-use clap::{Parser, Subcommand};
+mod doctor;
+mod exit_code;
+#[cfg(test)]
+mod fake_k8s;
+mod proxy_env;
+mod smoke_test;
+
+use std::io::{IsTerminal, Write};
+use std::process::ExitCode;
+use std::time::Duration;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use merge::Merge;
+use regex::Regex;
 use tokio::signal;
-use tracing::{debug, error, info, warn};
+use tokio::sync::watch;
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
 // Import traits from the new `k8socks-traits` crate
-use k8socks_traits::config::{Config, ConfigService};
-use k8socks_traits::k8s::{K8sService, PodRef};
+use k8socks_traits::config::{Config, ConfigService, PodResources};
+use k8socks_traits::k8s::{K8sService, PodRef, PodSummary};
 use k8socks_traits::logging::LoggingService;
-use k8socks_traits::ssh::SshService;
 
 // Import concrete implementations from the other crates
+use k8socks_cli::session::{
+    check_quota_before_deploy, delete_wait_timeout, expand_paths, record_session, render_command,
+};
+use k8socks_cli::{run_session_with, SessionEnd, SessionOptions};
+use k8socks_config::sessions::{validate_session_name, SessionStore};
 use k8socks_config::ConfigServiceImpl;
-use k8socks_k8s::K8sServiceImpl;
-use k8socks_logging::LoggingServiceImpl;
-use k8socks_ssh::SshServiceImpl;
+use k8socks_k8s::rbac::{rbac_manifests, RbacManifests};
+use k8socks_k8s::{
+    format_age, render_pod_manifest, resolve_cluster_target, K8sServiceImpl, PLACEHOLDER_PUBLIC_KEY,
+};
+use k8socks_logging::{effective_log_level, LoggingServiceImpl};
+use k8socks_ssh::build_ssh_command;
+use k8socks_ssh::relay::ProxyType;
+use k8socks_util::dotenv::parse_dotenv;
+use k8socks_util::duration::parse_duration;
+use k8socks_util::session::generate_session_id;
+
+use crate::exit_code::Interrupted;
+use crate::proxy_env::Emit;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -24,14 +50,17 @@ pub struct Cli {
 
     #[arg(long)]
     pub kubeconfig: Option<String>,
-    #[arg(long)]
-    pub context: Option<String>,
+    /// The kubeconfig context to use. Repeat it to deploy a proxy to each of
+    /// several clusters; `deploy` then uses consecutive local proxy ports.
+    #[arg(long = "context", value_name = "CONTEXT")]
+    pub contexts: Vec<String>,
     #[arg(long)]
     pub namespace: Option<String>,
     #[arg(long)]
     pub ssh_public_key_path: Option<String>,
     #[arg(long)]
     pub ssh_username: Option<String>,
+    /// Port of the local proxy that clients connect to (0 picks a free one).
     #[arg(long)]
     pub local_socks_port: Option<u16>,
     #[arg(long)]
@@ -40,6 +69,119 @@ pub struct Cli {
     pub pod_image: Option<String>,
     #[arg(long)]
     pub log_level: Option<String>,
+    /// Log more: `-v` for debug, `-vv` for trace. Overrides `--log-level`.
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+    /// Only log warnings and errors. Wins over `-v` and `--log-level`.
+    #[arg(short, long)]
+    pub quiet: bool,
+    #[arg(long)]
+    pub ssh_binary_path: Option<String>,
+    #[arg(long)]
+    pub ssh_connect_timeout_seconds: Option<u64>,
+    #[arg(long)]
+    pub pod_restart_policy: Option<String>,
+    #[arg(long)]
+    pub ssh_predial_attempts: Option<u32>,
+    #[arg(long)]
+    pub ssh_predial_interval_millis: Option<u64>,
+    #[arg(long)]
+    pub pod_image_kind: Option<String>,
+    #[arg(long)]
+    pub pod_ssh_port: Option<u16>,
+    #[arg(long)]
+    pub pod_uid: Option<u32>,
+    #[arg(long)]
+    pub pod_gid: Option<u32>,
+    /// Pass `-v` to ssh; repeat for `-vv`/`-vvv`.
+    #[arg(long, action = ArgAction::Count)]
+    pub ssh_verbose: u8,
+    /// On shutdown, wait up to this many seconds for active SOCKS connections to finish.
+    #[arg(long = "drain-timeout", value_name = "SECONDS")]
+    pub drain_timeout_seconds: Option<u64>,
+    #[arg(long)]
+    pub pod_ready_timeout_seconds: Option<u64>,
+    #[arg(long)]
+    pub image_pull_timeout_seconds: Option<u64>,
+    #[arg(long)]
+    pub pod_dns_policy: Option<String>,
+    #[arg(long)]
+    pub pod_selector_label: Option<String>,
+    #[arg(long)]
+    pub pod_max_lifetime_seconds: Option<u64>,
+    #[arg(long)]
+    pub ssh_jump_host: Option<String>,
+    /// The host `ssh` connects to on the forwarded port, instead of 127.0.0.1.
+    #[arg(long)]
+    pub ssh_target_host: Option<String>,
+    /// Ciphers `ssh` may use, passed as `-o Ciphers=...`.
+    #[arg(long, value_name = "LIST")]
+    pub ssh_ciphers: Option<String>,
+    /// Key exchange algorithms `ssh` may use, passed as `-o KexAlgorithms=...`.
+    #[arg(long, value_name = "LIST")]
+    pub ssh_kex_algorithms: Option<String>,
+    #[arg(long)]
+    pub pod_termination_grace_period_seconds: Option<i64>,
+    /// Comma-separated public key files to authorize; replaces `--ssh-public-key-path`.
+    #[arg(long, value_delimiter = ',')]
+    pub ssh_public_key_paths: Option<Vec<String>>,
+    #[arg(long)]
+    pub pod_poll_interval_millis: Option<u64>,
+    /// Protocol of the local proxy port: socks5 or http (HTTP CONNECT).
+    #[arg(long)]
+    pub proxy_type: Option<String>,
+    #[arg(long)]
+    pub pod_automount_service_account_token: Option<bool>,
+    /// Run the pod in the node's network namespace. Has security implications.
+    #[arg(long)]
+    pub pod_host_network: Option<bool>,
+    /// Generate sshd's host keys and authorized_keys in an init container, for
+    /// `sshd` images with a read-only root filesystem.
+    #[arg(long)]
+    pub pod_host_keys_init: bool,
+    /// Annotate the pod with when it expires, under this key, for external reapers.
+    #[arg(long, value_name = "KEY")]
+    pub pod_expiry_annotation_key: Option<String>,
+    /// How the expiry annotation is written: `rfc3339` (the expiry time) or `duration` (e.g. `900s`).
+    #[arg(long, value_name = "FORMAT")]
+    pub pod_expiry_annotation_format: Option<String>,
+    /// Hostname of the pod (`spec.hostname`), instead of the pod name.
+    #[arg(long, value_name = "NAME")]
+    pub pod_hostname: Option<String>,
+    /// Subdomain of the pod (`spec.subdomain`); name a headless Service to make the pod resolvable.
+    #[arg(long, value_name = "NAME")]
+    pub pod_subdomain: Option<String>,
+    /// End the session after this long, e.g. `90s`, `30m` or `1h30m`.
+    #[arg(long = "session-timeout", value_name = "DURATION", value_parser = parse_duration_secs)]
+    pub session_timeout_seconds: Option<u64>,
+    /// End the session once no proxy connection has been open for this long, e.g. `15m`.
+    #[arg(long = "idle-timeout", value_name = "DURATION", value_parser = parse_duration_secs)]
+    pub idle_timeout_seconds: Option<u64>,
+    /// Record the local user and hostname on the pod (`k8socks.io/created-by`, `k8socks.io/created-on`).
+    #[arg(long)]
+    pub annotate_identity: bool,
+    /// Log the destination host and port of every proxied connection.
+    #[arg(long)]
+    pub log_connections: bool,
+    /// Log the byte rate of every port-forward connection and the total at debug level.
+    #[arg(long)]
+    pub log_throughput: bool,
+    /// How often --log-throughput logs, e.g. `5s` or `1m`.
+    #[arg(long = "log-throughput-interval", value_name = "DURATION", value_parser = parse_duration_secs)]
+    pub log_throughput_interval_seconds: Option<u64>,
+    /// Serve `GET /healthz` on this local port: 200 while the proxy works, 503 otherwise.
+    #[arg(long)]
+    pub health_port: Option<u16>,
+    /// Keep the pod when k8socks exits, to reconnect to it later with `kubectl port-forward` and `ssh`.
+    #[arg(long)]
+    pub no_delete: bool,
+    /// Forward the local SSH agent to the pod (`ssh -A`). See the security notes.
+    #[arg(long)]
+    pub ssh_forward_agent: bool,
+    /// Local port of the port-forward to the pod's sshd, used only by ssh itself.
+    /// A free port is picked when unset. Not the proxy port; see --local-socks-port.
+    #[arg(long)]
+    pub forwarded_local_port: Option<u16>,
     #[arg(long)]
     pub config: Option<String>,
     #[arg(long)]
@@ -48,123 +190,855 @@ pub struct Cli {
     pub non_interactive: bool,
     #[arg(long)]
     pub dry_run: bool,
+    /// Delete pods immediately (grace period 0) instead of waiting for them to terminate.
+    #[arg(long)]
+    pub force_delete: bool,
+    /// After deleting a pod, wait until it is actually gone before exiting.
+    #[arg(long)]
+    pub wait: bool,
+    /// Create the pod and exit right away, printing `<namespace>/<pod>`, without
+    /// waiting for it or starting the proxy. The pod is kept until its TTL has passed.
+    #[arg(long, conflicts_with = "wait")]
+    pub no_wait: bool,
+    /// Before deploying, delete the k8socks pods already in the namespace (those
+    /// matching the pod selector), waiting until they are gone with `--wait`.
+    #[arg(long)]
+    pub replace: bool,
+    /// Keep the pod for inspection when the session ends with an error instead of Ctrl+C.
+    #[arg(long)]
+    pub keep_on_error: bool,
+    /// Refuse to deploy when the pod would exceed a resource quota of the namespace.
+    #[arg(long)]
+    pub strict_quota: bool,
+    /// Terminate ssh processes that earlier, crashed runs left behind instead of only warning about them.
+    #[arg(long)]
+    pub kill_orphans: bool,
+    /// Once the proxy is up, print `export ALL_PROXY=...` lines for the shell.
+    #[arg(long)]
+    pub emit_proxy_env: bool,
+    /// Once the proxy is up, write a PAC file for browsers to the temp directory.
+    #[arg(long)]
+    pub emit_pac: bool,
+    /// Print the effective configuration as JSON and exit without contacting the cluster.
+    #[arg(long)]
+    pub print_config: bool,
+    /// Load `KEY=VALUE` lines from a dotenv file into the environment before
+    /// reading the configuration. Variables already set are kept.
+    #[arg(long, value_name = "PATH")]
+    pub env_file: Option<String>,
+    /// Record the pod under this name and reuse it on the next `deploy` with
+    /// the same name while it is still running. The pod is kept on exit.
+    #[arg(long, value_name = "NAME")]
+    pub session_name: Option<String>,
+    /// Apply the settings of this profile of the config file's `profiles`.
+    /// Flags still take precedence.
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+    /// Wait a random 0 to N milliseconds before deleting the pod on shutdown,
+    /// so that many instances stopping at once do not hit the API server together.
+    #[arg(long, value_name = "MILLIS")]
+    pub cleanup_jitter_millis: Option<u64>,
+    /// Authorize a key from the running ssh-agent instead of a public key file.
+    #[arg(long = "use-agent-key")]
+    pub ssh_use_agent_key: bool,
+    /// Which agent key to authorize: its 0-based index in `ssh-add -l`, or
+    /// text its comment contains. Defaults to the first key.
+    #[arg(long = "agent-key", value_name = "INDEX_OR_COMMENT")]
+    pub ssh_agent_key: Option<String>,
+    /// Limit requests to the Kubernetes API to this many per second.
+    #[arg(long)]
+    pub kube_qps: Option<f64>,
+    /// With --kube-qps, how many requests may be made at once before the limit applies.
+    #[arg(long)]
+    pub kube_burst: Option<u32>,
+    /// PEM file of extra CA certificates to trust for the API server.
+    #[arg(long, value_name = "PATH")]
+    pub kube_ca_cert: Option<String>,
+    /// Do not verify the API server's certificate. Insecure; for dev clusters only.
+    #[arg(long)]
+    pub kube_insecure_skip_tls_verify: bool,
+    /// JSON merge patch applied to the pod's spec, e.g. '{"shareProcessNamespace": true}'.
+    #[arg(long, value_name = "JSON", value_parser = parse_json)]
+    pub pod_spec_patch: Option<serde_json::Value>,
+    /// Extra sshd_config line for the sshd image, e.g. 'MaxSessions 4'. Repeatable.
+    #[arg(long, value_name = "LINE")]
+    pub sshd_config: Vec<String>,
+    /// Close proxy connections beyond this many open ones.
+    #[arg(long)]
+    pub max_connections: Option<usize>,
+    /// Ask before deploying to a context whose name matches this regular expression, e.g. `prod`.
+    #[arg(long, value_name = "REGEX")]
+    pub production_context_pattern: Option<String>,
+    /// Pass this ssh config file to ssh with `-F`; by default `~/.ssh/config` is ignored unless --ssh-jump-host is set.
+    #[arg(long, value_name = "PATH")]
+    pub ssh_config_file: Option<String>,
+    /// Throttle the tunnel to this many bytes per second, both directions together.
+    #[arg(long, value_name = "BYTES_PER_SEC")]
+    pub rate_limit: Option<u64>,
+    /// The pod's resource requests, e.g. `cpu=250m,memory=256Mi`. Either may be left out.
+    #[arg(long, value_name = "cpu=..,memory=..")]
+    pub resources: Option<PodResources>,
+    /// Make the pod owned by a ConfigMap, so that deleting the ConfigMap deletes the pod.
+    #[arg(long)]
+    pub pod_owner_sentinel: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    /// A table for humans.
+    Table,
+    Json,
+    Yaml,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Deploys the SSH pod and starts the SOCKS5 proxy.
     Deploy,
+    /// Lists the k8socks pods currently running in the cluster.
+    Status {
+        /// Look for k8socks pods in every namespace instead of only the configured one.
+        #[arg(long)]
+        all_namespaces: bool,
+        /// How to print the pods.
+        #[arg(long, short = 'o', value_enum, default_value_t = OutputFormat::Table)]
+        output: OutputFormat,
+    },
+    /// Deletes leftover k8socks pods.
+    Cleanup {
+        /// Delete k8socks pods in every namespace instead of only the configured one.
+        #[arg(long)]
+        all_namespaces: bool,
+    },
+    /// Opens an interactive shell (or runs a command) in a k8socks pod.
+    Exec {
+        /// The pod to exec into. Defaults to the only k8socks pod in the namespace.
+        #[arg(long)]
+        pod: Option<String>,
+        /// The command to run, e.g. `k8socks exec -- ls /config`.
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Prints the sshd output of a k8socks pod.
+    Logs {
+        /// The pod to read. Defaults to the only k8socks pod in the namespace.
+        #[arg(long)]
+        pod: Option<String>,
+        /// Only output from this long ago on, e.g. `10m` or `1h`.
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration_secs)]
+        since: Option<u64>,
+        /// Only the last N lines.
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        tail: Option<u64>,
+    },
+    /// Checks that the local environment and cluster are ready for a deploy.
+    Doctor {
+        /// Print the results as JSON and exit non-zero unless all hard checks pass.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Deploys a pod, sends one request through the proxy and deletes the pod
+    /// again, printing PASS/FAIL and the time taken for each phase.
+    Test {
+        /// The URL fetched through the proxy. Only plain `http://` URLs are supported.
+        #[arg(long, default_value = smoke_test::DEFAULT_TEST_URL)]
+        test_url: String,
+        /// Stop once the proxy answers a SOCKS handshake, without making a request.
+        #[arg(long)]
+        skip_request: bool,
+    },
+    /// Prints the Role and RoleBinding that grant a service account what
+    /// k8socks needs in the configured namespace. Contacts no cluster.
+    Rbac {
+        /// The service account to bind, in the configured namespace.
+        #[arg(long, default_value = "k8socks")]
+        service_account: String,
+        /// Print a ClusterRole and ClusterRoleBinding instead, which
+        /// `--all-namespaces` and the namespace check before a deploy need.
+        #[arg(long)]
+        cluster_wide: bool,
+    },
+    /// Lists the profiles of the config file and what they set. Contacts no cluster.
+    Profiles,
+    /// Prints the pod manifest `deploy` would create, with a placeholder in
+    /// place of the public key. Contacts no cluster.
+    Manifest,
+}
+
+/// Sets the variables of a dotenv file that are not already set.
+fn load_env_file(path: &str) -> anyhow::Result<()> {
+    let expanded = ConfigServiceImpl::expand_tilde(path).unwrap_or_else(|| path.into());
+    let content = std::fs::read_to_string(&expanded)
+        .map_err(|e| anyhow::anyhow!("Failed to read env file '{}': {}", expanded.display(), e))?;
+    let vars = parse_dotenv(&content).map_err(|e| anyhow::anyhow!("Invalid env file '{}': {}", expanded.display(), e))?;
+    for (key, value) in vars {
+        if std::env::var_os(&key).is_none() {
+            std::env::set_var(key, value);
+        }
+    }
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+fn main() -> ExitCode {
+    match start(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // As anyhow would print it when returned from `main`.
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(exit_code::for_error(&e))
+        }
+    }
+}
+
+/// Loads the env file, then runs the command on a new runtime. `set_var` is
+/// only sound while no other threads may read the environment, so this has
+/// to happen before the runtime starts its worker threads.
+fn start(cli: Cli) -> anyhow::Result<()> {
+    if let Some(path) = cli.env_file.as_deref() {
+        load_env_file(path)?;
+    }
+    tokio::runtime::Runtime::new()?.block_on(run(cli))
+}
 
+async fn run(cli: Cli) -> anyhow::Result<()> {
     // --- Configuration Setup ---
     // Use the implementation of the `ConfigService` trait
     let file_config = ConfigServiceImpl::load_from_paths()?;
-    let cli_config = Config {
-        kubeconfig: cli.kubeconfig,
-        context: cli.context,
-        namespace: cli.namespace,
-        ssh_public_key_path: cli.ssh_public_key_path,
-        ssh_username: cli.ssh_username,
-        local_socks_port: cli.local_socks_port,
-        pod_ttl_seconds: cli.pod_ttl_seconds,
-        pod_image: cli.pod_image,
-        pod_resources: None,
-        pod_labels: None,
-        pod_annotations: None,
-        log_level: cli.log_level,
-    };
+    let cli_config = Config::builder()
+        .kubeconfig(cli.kubeconfig)
+        .context(cli.contexts.first().cloned())
+        .contexts((!cli.contexts.is_empty()).then(|| cli.contexts.clone()))
+        .namespace(cli.namespace)
+        .ssh_public_key_path(cli.ssh_public_key_path)
+        .ssh_username(cli.ssh_username)
+        .local_socks_port(cli.local_socks_port)
+        .pod_ttl_seconds(cli.pod_ttl_seconds)
+        .pod_image(cli.pod_image)
+        .log_level(cli.log_level)
+        .ssh_binary_path(cli.ssh_binary_path)
+        .ssh_connect_timeout_seconds(cli.ssh_connect_timeout_seconds)
+        .pod_restart_policy(cli.pod_restart_policy)
+        .ssh_predial_attempts(cli.ssh_predial_attempts)
+        .ssh_predial_interval_millis(cli.ssh_predial_interval_millis)
+        .pod_image_kind(cli.pod_image_kind)
+        .pod_ssh_port(cli.pod_ssh_port)
+        .pod_uid(cli.pod_uid)
+        .pod_gid(cli.pod_gid)
+        .ssh_verbosity((cli.ssh_verbose > 0).then_some(cli.ssh_verbose))
+        .drain_timeout_seconds(cli.drain_timeout_seconds)
+        .pod_ready_timeout_seconds(cli.pod_ready_timeout_seconds)
+        .image_pull_timeout_seconds(cli.image_pull_timeout_seconds)
+        .pod_dns_policy(cli.pod_dns_policy)
+        .pod_selector_label(cli.pod_selector_label)
+        .pod_max_lifetime_seconds(cli.pod_max_lifetime_seconds)
+        .ssh_jump_host(cli.ssh_jump_host)
+        .ssh_target_host(cli.ssh_target_host)
+        .ssh_ciphers(cli.ssh_ciphers)
+        .ssh_kex_algorithms(cli.ssh_kex_algorithms)
+        .pod_termination_grace_period_seconds(cli.pod_termination_grace_period_seconds)
+        .ssh_public_key_paths(cli.ssh_public_key_paths)
+        .pod_poll_interval_millis(cli.pod_poll_interval_millis)
+        .proxy_type(cli.proxy_type)
+        .pod_automount_service_account_token(cli.pod_automount_service_account_token)
+        .pod_host_network(cli.pod_host_network)
+        .pod_host_keys_init(cli.pod_host_keys_init.then_some(true))
+        .pod_expiry_annotation_key(cli.pod_expiry_annotation_key)
+        .pod_expiry_annotation_format(cli.pod_expiry_annotation_format)
+        .pod_hostname(cli.pod_hostname)
+        .pod_subdomain(cli.pod_subdomain)
+        .session_timeout_seconds(cli.session_timeout_seconds)
+        .forwarded_local_port(cli.forwarded_local_port)
+        .idle_timeout_seconds(cli.idle_timeout_seconds)
+        .annotate_identity(cli.annotate_identity.then_some(true))
+        .log_connections(cli.log_connections.then_some(true))
+        .log_throughput(cli.log_throughput.then_some(true))
+        .log_throughput_interval_seconds(cli.log_throughput_interval_seconds)
+        .health_port(cli.health_port)
+        .delete_pod_on_exit(cli.no_delete.then_some(false))
+        .cleanup_jitter_millis(cli.cleanup_jitter_millis)
+        .ssh_use_agent_key(cli.ssh_use_agent_key.then_some(true))
+        .ssh_agent_key(cli.ssh_agent_key)
+        .ssh_forward_agent(cli.ssh_forward_agent.then_some(true))
+        .kube_qps(cli.kube_qps)
+        .kube_burst(cli.kube_burst)
+        .kube_ca_cert_path(cli.kube_ca_cert)
+        .kube_insecure_skip_tls_verify(cli.kube_insecure_skip_tls_verify.then_some(true))
+        .pod_spec_patch(cli.pod_spec_patch)
+        .sshd_config((!cli.sshd_config.is_empty()).then(|| cli.sshd_config.join("\n")))
+        .max_connections(cli.max_connections)
+        .production_context_pattern(cli.production_context_pattern)
+        .ssh_config_file(cli.ssh_config_file)
+        .rate_limit(cli.rate_limit)
+        .pod_resources(cli.resources)
+        .pod_owner_sentinel(cli.pod_owner_sentinel.then_some(true))
+        .build();
     let mut config = Config::default();
     config.merge(file_config);
+    if let Some(profile) = &cli.profile {
+        config.apply_profile(profile)?;
+    }
     config.merge(cli_config);
+    config.validate()?;
 
     // --- Logging ---
     // Use the implementation of the `LoggingService` trait
-    LoggingServiceImpl::init_logging(config.log_level.as_deref().unwrap_or("info"), !cli.no_color)
+    let log_level = effective_log_level(config.log_level.as_deref().unwrap_or("info"), cli.verbose, cli.quiet);
+    LoggingServiceImpl::init_logging(log_level, !cli.no_color)
         .map_err(|e| anyhow::anyhow!("Failed to initialize logging: {}", e))?;
 
     // --- Path Expansion ---
-    // Use the implementation of the `ConfigService` trait
-    if let Some(path) = config.kubeconfig.clone() {
-        config.kubeconfig = Some(ConfigServiceImpl::expand_tilde(&path).unwrap().to_string_lossy().into_owned());
+    expand_paths(&mut config)?;
+
+    debug!("Final configuration: {:#?}", config);
+
+    if cli.print_config {
+        println!("{}", serde_json::to_string_pretty(&config)?);
+        return Ok(());
     }
-    if let Some(path) = config.ssh_public_key_path.clone() {
-        config.ssh_public_key_path = Some(ConfigServiceImpl::expand_tilde(&path).unwrap().to_string_lossy().into_owned());
+
+    let delete_grace_period = cli.force_delete.then_some(0);
+    let interactive = !cli.non_interactive && !cli.dry_run && std::io::stdin().is_terminal();
+    if matches!(cli.command, Commands::Deploy | Commands::Test { .. }) {
+        confirm_cluster_targets(&config, interactive).await?;
     }
+    match cli.command {
+        Commands::Deploy if cli.no_wait => {
+            if cli.replace {
+                replace_pods(&config, cli.dry_run, delete_grace_period, false).await?;
+            }
+            deploy_only(&config, cli.dry_run, cli.strict_quota, cli.session_name.as_deref()).await
+        }
+        Commands::Deploy => {
+            if cli.replace {
+                replace_pods(&config, cli.dry_run, delete_grace_period, cli.wait).await?;
+            }
+            let options = SessionOptions {
+                delete_grace_period,
+                wait_for_delete: cli.wait,
+                keep_on_error: cli.keep_on_error,
+                strict_quota: cli.strict_quota,
+                session_name: cli.session_name,
+                kill_orphans: cli.kill_orphans,
+            };
+            let emit = Emit {
+                env: cli.emit_proxy_env,
+                pac: cli.emit_pac,
+            };
+            deploy(&config, cli.dry_run, options, emit).await
+        }
+        Commands::Status { all_namespaces, output } => status(&config, all_namespaces, output).await,
+        Commands::Cleanup { all_namespaces } => {
+            cleanup(&config, all_namespaces, delete_grace_period, cli.wait).await
+        }
+        Commands::Exec { pod, command } => exec(&config, pod, command).await,
+        Commands::Logs { pod, since, tail } => logs(&config, pod, since, tail).await,
+        Commands::Doctor { check } => run_doctor(&config, check).await,
+        Commands::Test { test_url, skip_request } => {
+            run_smoke_test(&config, &test_url, skip_request, delete_grace_period).await
+        }
+        Commands::Rbac {
+            service_account,
+            cluster_wide,
+        } => print_rbac(&config, &service_account, cluster_wide),
+        Commands::Profiles => {
+            print_profiles(&config);
+            Ok(())
+        }
+        Commands::Manifest => print_manifest(&config),
+    }
+}
 
-    debug!("Final configuration: {:#?}", config);
+/// Shows the cluster each context is about to be deployed to, before anything
+/// is created there. On a terminal, a context matching
+/// `production_context_pattern` must be confirmed first.
+async fn confirm_cluster_targets(config: &Config, interactive: bool) -> anyhow::Result<()> {
+    let pattern = config.production_context_pattern.as_deref().map(Regex::new).transpose()?;
+    for session in config.per_context() {
+        let target = resolve_cluster_target(&session).await?;
+        let context = target.context.as_deref().unwrap_or("(in-cluster)");
+        info!("==================================================");
+        info!("  Context:   {}", context);
+        info!("  Cluster:   {}", target.server);
+        info!("  Namespace: {}", target.namespace);
+        info!("==================================================");
+        let production = pattern
+            .as_ref()
+            .zip(target.context.as_deref())
+            .is_some_and(|(pattern, context)| pattern.is_match(context));
+        if !production {
+            continue;
+        }
+        if !interactive {
+            warn!("Context '{}' matches production_context_pattern; continuing without confirmation", context);
+            continue;
+        }
+        let question = format!("Context '{}' looks like production. Deploy to it?", context);
+        if !confirm(&question)? {
+            anyhow::bail!("Not deploying to context '{}': not confirmed", context);
+        }
+    }
+    Ok(())
+}
 
-    if cli.dry_run {
+/// Asks a yes/no question on the terminal; anything but `y` or `yes` is no.
+fn confirm(question: &str) -> anyhow::Result<bool> {
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Prints a table of the configured profiles with the settings that tell them
+/// apart most often. `-` marks a setting the profile leaves alone.
+fn print_profiles(config: &Config) {
+    let Some(profiles) = config.profiles.as_ref().filter(|p| !p.is_empty()) else {
+        println!("No profiles configured. Add a `profiles` table to the config file to define some.");
+        return;
+    };
+    let or_dash = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+    println!("{:<20} {:<20} {:<24} IMAGE", "NAME", "NAMESPACE", "CONTEXT");
+    for (name, profile) in profiles {
+        println!(
+            "{:<20} {:<20} {:<24} {}",
+            name,
+            or_dash(&profile.namespace),
+            or_dash(&profile.context),
+            or_dash(&profile.pod_image)
+        );
+    }
+}
+
+async fn deploy(config: &Config, dry_run: bool, options: SessionOptions, emit: Emit) -> anyhow::Result<()> {
+    let sessions = config.per_context();
+    let session_name = options.session_name.as_deref();
+    if let Some(name) = session_name {
+        validate_session_name(name).map_err(|e| anyhow::anyhow!(e))?;
+        if sessions.len() > 1 {
+            anyhow::bail!("--session-name cannot be combined with several contexts");
+        }
+    }
+    if dry_run {
+        if sessions.len() > 1 {
+            for session in &sessions {
+                info!(
+                    "[dry-run] Context '{}': proxy on port {}",
+                    session.context.as_deref().unwrap_or_default(),
+                    session.local_socks_port.unwrap_or(1080)
+                );
+            }
+            info!("[dry-run] For each context:");
+        }
         info!("[dry-run] Would execute the following steps:");
         info!("[dry-run] 1. Connect to Kubernetes cluster");
-        info!("[dry-run] 2. Deploy a pod with image '{}'", config.pod_image.as_ref().unwrap());
+        match session_name {
+            Some(name) => info!(
+                "[dry-run] 2. Reuse the running pod of session '{}', or deploy a pod with image '{}'",
+                name,
+                config.pod_image.as_ref().unwrap()
+            ),
+            None => info!("[dry-run] 2. Deploy a pod with image '{}'", config.pod_image.as_ref().unwrap()),
+        }
         info!("[dry-run] 3. Wait for pod to become ready");
-        info!("[dry-run] 4. Establish port-forward to pod:22");
-        info!("[dry-run] 5. Start local SSH SOCKS5 proxy on port {}", config.local_socks_port.unwrap_or(1080));
-        info!("[dry-run] 6. On exit, delete the pod");
+        info!("[dry-run] 4. Establish port-forward to the pod's SSH port");
+        match config.local_socks_port.unwrap_or(1080) {
+            0 => info!("[dry-run] 5. Start local SSH SOCKS5 proxy on a free port picked at startup"),
+            port => info!("[dry-run] 5. Start local SSH SOCKS5 proxy on port {}", port),
+        }
+        // ssh serves SOCKS on an internal port behind the relay, and the
+        // forwarded port is only known once the port-forward is up.
+        let ssh_command = build_ssh_command(config, "<internal-socks-port>", "<forwarded-port>");
+        info!("[dry-run]    {}", render_command(&ssh_command));
+        if config.delete_pod_on_exit.unwrap_or(true) && session_name.is_none() {
+            info!("[dry-run] 6. On exit, delete the pod");
+        } else {
+            info!("[dry-run] 6. On exit, keep the pod running until its TTL has passed");
+        }
         return Ok(());
     }
 
-    // --- Main Application Logic ---
-    // Instantiate the concrete implementations of the services
-    let k8s_service = K8sServiceImpl::new(&config).await?;
-    let pod_ref = deploy_and_wait(&k8s_service).await?;
+    // Set when a session fails or gets a shutdown signal, so that the
+    // sessions in the other contexts end too, even those still deploying
+    let (end_all, _) = watch::channel(false);
 
-    // Set up graceful shutdown
-    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
-    let k8s_service_clone = k8s_service.clone();
-    let pod_ref_clone = pod_ref.clone();
+    // Everything logged during a session carries its id, and the pod name
+    // once the pod exists
+    if let [config] = sessions.as_slice() {
+        let span = info_span!("session", session_id = %generate_session_id(), pod = tracing::field::Empty);
+        return supervise_session(config, options, emit, &end_all).instrument(span).await;
+    }
 
-    tokio::spawn(async move {
-        signal::ctrl_c().await.expect("Failed to listen for ctrl-c");
-        warn!("Received shutdown signal. Cleaning up...");
-        if let Err(e) = k8s_service_clone.delete_pod(&pod_ref_clone).await {
-            error!("Failed to delete pod during shutdown: {}", e);
+    let names: Vec<&str> = sessions.iter().filter_map(|s| s.context.as_deref()).collect();
+    info!("Deploying to {} contexts: {}", sessions.len(), names.join(", "));
+    let runs = sessions.iter().map(|session| {
+        let span = info_span!(
+            "session",
+            session_id = %generate_session_id(),
+            context = %session.context.as_deref().unwrap_or_default(),
+            pod = tracing::field::Empty
+        );
+        let (options, end_all) = (options.clone(), &end_all);
+        async move {
+            let result = supervise_session(session, options, emit, end_all).await;
+            if let Err(e) = &result {
+                error!("Session failed: {:#}", e);
+                end_all.send_replace(true);
+            }
+            result
         }
-        tx.send(()).await.ok();
+        .instrument(span)
     });
+    let results = futures::future::join_all(runs).await;
+    let failed = results.iter().filter(|r| r.is_err()).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} sessions failed", failed, results.len());
+    }
+    Ok(())
+}
 
-    // Start port forwarding and the SSH proxy
-    // Let the OS pick an ephemeral port for the SSH connection
-    let pf_handle = k8s_service.port_forward(&pod_ref, 0).await?;
-    info!("Established port-forward to pod on 127.0.0.1:{}", pf_handle.local_port);
-    let ssh_service = SshServiceImpl::new(&config);
-    let ssh_handle = ssh_service.start_socks_proxy(pf_handle.local_port).await?;
-    info!("SOCKS5 proxy is now running on 127.0.0.1:{}", config.local_socks_port.unwrap_or(1080));
+/// Runs a session until it ends. Ctrl+C shuts it down, a second one without
+/// waiting for connections to drain, and so does the end of any session in
+/// `end_all`; a failing ssh ends the others.
+async fn supervise_session(
+    config: &Config,
+    options: SessionOptions,
+    emit: Emit,
+    end_all: &watch::Sender<bool>,
+) -> anyhow::Result<()> {
+    let session = run_session_with(config.clone(), options).await?;
+    // `validate` has already rejected unknown proxy types
+    let proxy_type = config
+        .proxy_type
+        .as_deref()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(ProxyType::Socks5);
+    if emit.env {
+        println!("{}", proxy_env::env_snippet(proxy_type, session.socks_port()));
+    }
+    let pac_path = if emit.pac {
+        match proxy_env::write_pac_file(proxy_type, session.socks_port()) {
+            Ok(path) => {
+                info!("Wrote PAC file; point your browser at file://{}", path.display());
+                Some(path)
+            }
+            Err(e) => {
+                warn!("Failed to write the PAC file: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
     info!("Press Ctrl+C to exit.");
 
-    // Wait for either the SSH process to exit or for a shutdown signal
-    tokio::select! {
-        res = ssh_service.watch(ssh_handle) => {
-            if let Err(e) = res {
-                error!("SSH process failed: {}", e);
+    let trigger = session.shutdown_trigger();
+    let signals = async {
+        // Counted here, as another session may have started the shutdown
+        let mut signalled = false;
+        while signal::ctrl_c().await.is_ok() {
+            if signalled {
+                warn!("Received second shutdown signal.");
+                trigger.shutdown_now();
+            } else {
+                warn!("Received shutdown signal.");
+                signalled = true;
+                trigger.shutdown();
+                end_all.send_replace(true);
             }
         }
-        _ = rx.recv() => {
-            info!("Shutdown complete.");
+        std::future::pending::<()>().await
+    };
+    let mut end_all_rx = end_all.subscribe();
+    let others = async {
+        if end_all_rx.wait_for(|end| *end).await.is_ok() && trigger.shutdown() {
+            warn!("Another session is ending.");
+        }
+        std::future::pending::<()>().await
+    };
+    let end = tokio::select! {
+        end = session.wait() => end?,
+        _ = signals => unreachable!("signals are forwarded until the session ends"),
+        _ = others => unreachable!("another session ending is forwarded until the session ends"),
+    };
+    if end == SessionEnd::SshFailed {
+        end_all.send_replace(true);
+    }
+    if let Some(path) = pac_path {
+        if let Err(e) = std::fs::remove_file(&path) {
+            debug!("Failed to remove the PAC file {}: {}", path.display(), e);
         }
     }
+    Ok(())
+}
 
-    // Final cleanup in case of non-Ctrl+C exit
-    if rx.try_recv().is_err() {
-        info!("Cleaning up pod...");
-        if let Err(e) = k8s_service.delete_pod(&pod_ref).await {
-            error!("Failed to delete pod on exit: {}", e);
+/// Creates the pod of every context and exits without waiting for it, for
+/// starting the proxy elsewhere. No cleanup is registered: the pod runs until
+/// its TTL has passed or it is deleted with `cleanup`.
+async fn deploy_only(
+    config: &Config,
+    dry_run: bool,
+    strict_quota: bool,
+    session_name: Option<&str>,
+) -> anyhow::Result<()> {
+    let sessions = config.per_context();
+    if let Some(name) = session_name {
+        validate_session_name(name).map_err(|e| anyhow::anyhow!(e))?;
+        if sessions.len() > 1 {
+            anyhow::bail!("--session-name cannot be combined with several contexts");
+        }
+    }
+    if dry_run {
+        info!("[dry-run] Would execute the following steps:");
+        info!("[dry-run] 1. Connect to Kubernetes cluster");
+        info!("[dry-run] 2. Deploy a pod with image '{}'", config.pod_image.as_ref().unwrap());
+        info!("[dry-run] 3. Exit, keeping the pod running until its TTL has passed");
+        return Ok(());
+    }
+    for session in &sessions {
+        let k8s_service = K8sServiceImpl::new(session).await?;
+        check_quota_before_deploy(&k8s_service, strict_quota).await?;
+        let pod_ref = k8s_service.deploy_pod().await?;
+        info!("Pod '{}' created in namespace '{}'; not waiting for it.", pod_ref.name, pod_ref.namespace);
+        if let Some(name) = session_name {
+            match SessionStore::in_home_dir() {
+                Some(store) => record_session(&store, name, session, &pod_ref),
+                None => warn!("Cannot record session '{}': the home directory is unknown", name),
+            }
+        }
+        println!("{}/{}", pod_ref.namespace, pod_ref.name);
+    }
+    Ok(())
+}
+
+fn parse_duration_secs(input: &str) -> Result<u64, String> {
+    parse_duration(input).map(|d| d.as_secs())
+}
+
+fn parse_json(input: &str) -> Result<serde_json::Value, String> {
+    serde_json::from_str(input).map_err(|e| format!("invalid JSON: {}", e))
+}
+
+fn print_rbac(config: &Config, service_account: &str, cluster_wide: bool) -> anyhow::Result<()> {
+    // Without a cluster to ask, an unset namespace is `default` rather than
+    // that of the kubeconfig context.
+    let namespace = config.namespace.as_deref().unwrap_or("default");
+    let documents = match rbac_manifests(namespace, service_account, cluster_wide) {
+        RbacManifests::Namespaced { role, binding } => {
+            [serde_yaml::to_string(&role)?, serde_yaml::to_string(&binding)?]
+        }
+        RbacManifests::ClusterWide { role, binding } => {
+            [serde_yaml::to_string(&role)?, serde_yaml::to_string(&binding)?]
+        }
+    };
+    print!("{}", documents.join("---\n"));
+    Ok(())
+}
+
+fn print_manifest(config: &Config) -> anyhow::Result<()> {
+    let pod = render_pod_manifest(config)?;
+    println!("# The public key below is a placeholder ({}).", PLACEHOLDER_PUBLIC_KEY);
+    println!("# Replace it with the contents of your public key file before applying.");
+    print!("{}", serde_yaml::to_string(&pod)?);
+    Ok(())
+}
+
+async fn status(config: &Config, all_namespaces: bool, output: OutputFormat) -> anyhow::Result<()> {
+    let k8s_service = K8sServiceImpl::new(config).await?;
+    let summaries: Vec<PodSummary> = k8s_service
+        .list_pods(all_namespaces)
+        .await?
+        .iter()
+        .map(PodSummary::from)
+        .collect();
+    match output {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&summaries)?);
+            return Ok(());
+        }
+        OutputFormat::Yaml => {
+            print!("{}", serde_yaml::to_string(&summaries)?);
+            return Ok(());
         }
+        OutputFormat::Table => {}
     }
+    if summaries.is_empty() {
+        info!("No k8socks pods found.");
+        return Ok(());
+    }
+    println!("NAMESPACE                NAME                 PHASE      NODE                 AGE");
+    for pod in &summaries {
+        println!(
+            "{:<24} {:<20} {:<10} {:<20} {}",
+            pod.namespace,
+            pod.name,
+            pod.phase.as_deref().unwrap_or("-"),
+            pod.node.as_deref().unwrap_or("-"),
+            pod.age_seconds
+                .map(|secs| format_age(Duration::from_secs(secs)))
+                .unwrap_or_else(|| "-".to_string()),
+        );
+    }
+    Ok(())
+}
+
+async fn cleanup(
+    config: &Config,
+    all_namespaces: bool,
+    delete_grace_period: Option<u32>,
+    wait: bool,
+) -> anyhow::Result<()> {
+    let k8s_service = K8sServiceImpl::new(config).await?;
+    let pods = k8s_service.list_pods(all_namespaces).await?;
+    if pods.is_empty() {
+        info!("No k8socks pods to clean up.");
+        return Ok(());
+    }
+    delete_pods(&k8s_service, config, &pods, delete_grace_period, wait).await;
+    Ok(())
+}
 
+/// Deletes the k8socks pods of each context's namespace ahead of a deploy, for
+/// `--replace`. Like `cleanup`, only pods matching the pod selector are touched.
+async fn replace_pods(
+    config: &Config,
+    dry_run: bool,
+    delete_grace_period: Option<u32>,
+    wait: bool,
+) -> anyhow::Result<()> {
+    for session in config.per_context() {
+        let namespace = session.namespace.as_deref().unwrap_or_default();
+        if dry_run {
+            info!("[dry-run] Would first delete the k8socks pods in namespace '{}'", namespace);
+            continue;
+        }
+        let k8s_service = K8sServiceImpl::new(&session).await?;
+        let pods = k8s_service.list_pods(false).await?;
+        if pods.is_empty() {
+            debug!("No k8socks pods to replace in namespace '{}'", namespace);
+            continue;
+        }
+        info!("Replacing {} existing pod(s) in namespace '{}'", pods.len(), namespace);
+        delete_pods(&k8s_service, &session, &pods, delete_grace_period, wait).await;
+    }
     Ok(())
 }
 
-// Update `deploy_and_wait` to be generic over any type that implements `K8sService`
-async fn deploy_and_wait<K: K8sService>(k8s_service: &K) -> anyhow::Result<PodRef> {
-    info!("Deploying SSH server pod...");
-    let pod_ref = k8s_service.deploy_pod().await?;
-    info!("Pod '{}' created in namespace '{}'. Waiting for it to be ready...", pod_ref.name, pod_ref.namespace);
-    k8s_service.wait_for_pod_ready(&pod_ref).await?;
-    info!("Pod is running and ready.");
+/// Deletes `pods`, optionally waits until they are gone, and forgets the
+/// sessions that used them. Failures are logged, not returned.
+async fn delete_pods(
+    k8s_service: &K8sServiceImpl,
+    config: &Config,
+    pods: &[PodRef],
+    delete_grace_period: Option<u32>,
+    wait: bool,
+) {
+    let mut deleted = Vec::new();
+    for pod in pods {
+        info!("Deleting pod '{}' in namespace '{}'...", pod.name, pod.namespace);
+        match k8s_service.delete_pod(pod, delete_grace_period).await {
+            Ok(()) => deleted.push(pod),
+            Err(e) => error!("Failed to delete pod '{}': {}", pod.name, e),
+        }
+    }
+    if wait {
+        let timeout = delete_wait_timeout(config, delete_grace_period);
+        for pod in &deleted {
+            if let Err(e) = k8s_service.wait_for_pod_deleted(pod, timeout).await {
+                error!("{}", e);
+            }
+        }
+    }
+    // Forget the sessions whose pods are gone
+    if let Some(store) = SessionStore::in_home_dir() {
+        for record in store.list() {
+            if deleted.iter().any(|pod| pod.name == record.pod && pod.namespace == record.namespace) {
+                if let Err(e) = store.remove(&record.name) {
+                    warn!("Failed to remove the record of session '{}': {}", record.name, e);
+                }
+            }
+        }
+    }
+}
+
+/// Finds the k8socks pod named `pod`, or the only one in the namespace.
+async fn find_pod(k8s_service: &K8sServiceImpl, pod: Option<String>) -> anyhow::Result<PodRef> {
+    let pods = k8s_service.list_pods(false).await?;
+    let pod_ref = match pod {
+        Some(name) => pods
+            .into_iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| anyhow::anyhow!("No k8socks pod named '{}' found", name))?,
+        None => match pods.as_slice() {
+            [pod_ref] => pod_ref.clone(),
+            [] => anyhow::bail!("No k8socks pod found. Deploy one first or pass --pod."),
+            _ => anyhow::bail!("Found {} k8socks pods; choose one with --pod.", pods.len()),
+        },
+    };
     Ok(pod_ref)
-}
\ No newline at end of file
+}
+
+async fn exec(config: &Config, pod: Option<String>, command: Vec<String>) -> anyhow::Result<()> {
+    let k8s_service = K8sServiceImpl::new(config).await?;
+    let pod_ref = find_pod(&k8s_service, pod).await?;
+    let command = if command.is_empty() {
+        vec!["/bin/sh".to_string()]
+    } else {
+        command
+    };
+
+    let tty = std::io::stdin().is_terminal();
+    info!("Executing {:?} in pod '{}'...", command, pod_ref.name);
+    if tty {
+        crossterm::terminal::enable_raw_mode()?;
+    }
+    let result = k8s_service.exec(&pod_ref, command, tty).await;
+    if tty {
+        crossterm::terminal::disable_raw_mode()?;
+    }
+    result?;
+    Ok(())
+}
+
+async fn logs(config: &Config, pod: Option<String>, since: Option<u64>, tail: Option<u64>) -> anyhow::Result<()> {
+    let k8s_service = K8sServiceImpl::new(config).await?;
+    let pod_ref = find_pod(&k8s_service, pod).await?;
+    let output = k8s_service
+        .pod_logs(&pod_ref, since.map(Duration::from_secs), tail)
+        .await?;
+    print!("{}", output);
+    Ok(())
+}
+
+async fn run_doctor(config: &Config, check: bool) -> anyhow::Result<()> {
+    let results = doctor::run_checks(config).await;
+    if !check {
+        doctor::print_table(&results);
+        return Ok(());
+    }
+    println!("{}", serde_json::to_string_pretty(&results)?);
+    if !doctor::all_hard_checks_passed(&results) {
+        anyhow::bail!("Not all required checks passed");
+    }
+    Ok(())
+}
+
+async fn run_smoke_test(
+    config: &Config,
+    test_url: &str,
+    skip_request: bool,
+    delete_grace_period: Option<u32>,
+) -> anyhow::Result<()> {
+    // Reject a bad URL before deploying anything.
+    if !skip_request {
+        smoke_test::parse_http_url(test_url)?;
+    }
+    let phases = smoke_test::run(config, (!skip_request).then_some(test_url), delete_grace_period).await;
+    let passed = smoke_test::print_report(&phases);
+    if phases.iter().any(|phase| phase.name == smoke_test::INTERRUPTED_PHASE) {
+        return Err(Interrupted.into());
+    }
+    if !passed {
+        anyhow::bail!("The smoke test failed");
+    }
+    Ok(())
+}
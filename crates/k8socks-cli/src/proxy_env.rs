@@ -0,0 +1,73 @@
+//! Ways to point other tools at the proxy once it is up: shell `export`
+//! lines (`--emit-proxy-env`) and a PAC file for browsers (`--emit-pac`).
+
+use std::io;
+use std::path::PathBuf;
+
+use k8socks_ssh::relay::ProxyType;
+
+/// What to emit once the proxy is up.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Emit {
+    pub env: bool,
+    pub pac: bool,
+}
+
+/// `export` lines for the proxy, one per variable, that can be `eval`ed.
+/// An HTTP proxy only tunnels `CONNECT`, so it is not offered for plain HTTP.
+pub fn env_snippet(proxy_type: ProxyType, port: u16) -> String {
+    let (url, variables): (String, &[&str]) = match proxy_type {
+        ProxyType::Socks5 => (format!("socks5://127.0.0.1:{}", port), &["ALL_PROXY", "all_proxy"]),
+        ProxyType::Http => (
+            format!("http://127.0.0.1:{}", port),
+            &["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"],
+        ),
+    };
+    variables
+        .iter()
+        .map(|variable| format!("export {}={}", variable, url))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A PAC file that sends all traffic through the proxy.
+pub fn pac_file(proxy_type: ProxyType, port: u16) -> String {
+    let proxy = match proxy_type {
+        ProxyType::Socks5 => format!("SOCKS5 127.0.0.1:{port}; SOCKS 127.0.0.1:{port}"),
+        ProxyType::Http => format!("PROXY 127.0.0.1:{port}"),
+    };
+    format!("function FindProxyForURL(url, host) {{\n    return \"{}\";\n}}\n", proxy)
+}
+
+/// Writes the PAC file for `port` to the temp directory and returns its path.
+pub fn write_pac_file(proxy_type: ProxyType, port: u16) -> io::Result<PathBuf> {
+    let path = std::env::temp_dir().join(format!("k8socks-{}.pac", port));
+    std::fs::write(&path, pac_file(proxy_type, port))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_snippet() {
+        assert_eq!(
+            env_snippet(ProxyType::Socks5, 1080),
+            "export ALL_PROXY=socks5://127.0.0.1:1080\nexport all_proxy=socks5://127.0.0.1:1080"
+        );
+        let http = env_snippet(ProxyType::Http, 3128);
+        assert!(http.starts_with("export HTTPS_PROXY=http://127.0.0.1:3128\n"), "{}", http);
+        assert!(!http.contains("HTTP_PROXY="), "{}", http);
+        assert!(http.lines().all(|line| line.starts_with("export ")));
+    }
+
+    #[test]
+    fn test_pac_file() {
+        assert_eq!(
+            pac_file(ProxyType::Socks5, 1080),
+            "function FindProxyForURL(url, host) {\n    return \"SOCKS5 127.0.0.1:1080; SOCKS 127.0.0.1:1080\";\n}\n"
+        );
+        assert!(pac_file(ProxyType::Http, 3128).contains("return \"PROXY 127.0.0.1:3128\";"));
+    }
+}
@@ -0,0 +1,814 @@
+//! A proxy session from deploying the pod to deleting it again: what `deploy`
+//! runs for each context, usable from other programs through
+//! [`run_session`].
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn, Instrument, Span};
+
+use k8socks_traits::config::{Config, ConfigService};
+use k8socks_traits::k8s::{K8sError, K8sService, PodRef, PortForwardHandle, TransferStats};
+use k8socks_traits::ssh::{SshProcessHandle, SshService};
+
+use k8socks_config::sessions::{is_stale, SessionRecord, SessionStore};
+use k8socks_config::ConfigServiceImpl;
+use k8socks_k8s::{
+    is_retryable, pod_problem_messages, ssh_container_port, throughput_lines, wait_for_sshd, K8sServiceImpl,
+};
+use k8socks_ssh::health;
+use k8socks_ssh::orphans::{find_orphaned_ssh, terminate};
+use k8socks_ssh::relay::{ProxyType, SocksRelay};
+use k8socks_ssh::{build_ssh_command, SshServiceImpl};
+use k8socks_util::retry::{random_delay, retry_with_backoff_if, BackoffPolicy};
+
+use crate::timings::PhaseTimings;
+
+/// How a session is run and torn down, beyond its `Config`.
+#[derive(Clone, Debug, Default)]
+pub struct SessionOptions {
+    /// The grace period for deleting the pod; `None` keeps the pod's own.
+    pub delete_grace_period: Option<u32>,
+    /// Wait until the deleted pod is gone before the session ends.
+    pub wait_for_delete: bool,
+    /// Keep the pod for inspection when ssh fails.
+    pub keep_on_error: bool,
+    /// Fail instead of warn when the pod would exceed a resource quota.
+    pub strict_quota: bool,
+    /// Reuse the pod recorded under this name, and keep it on exit.
+    pub session_name: Option<String>,
+    /// Terminate ssh processes left behind by earlier runs instead of only
+    /// warning about them.
+    pub kill_orphans: bool,
+}
+
+/// Why a session ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionEnd {
+    /// ssh exited by itself.
+    SshExited,
+    /// ssh exited with an error.
+    SshFailed,
+    /// Shutdown was requested through the handle, or the handle was dropped.
+    Shutdown,
+    /// `session_timeout_seconds` has passed.
+    SessionTimeout,
+    /// No proxy connections for `idle_timeout_seconds`.
+    IdleTimeout,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stop {
+    Running,
+    /// Stop accepting connections and let active ones finish within
+    /// `drain_timeout_seconds`.
+    Drain,
+    /// Close active connections right away.
+    Now,
+}
+
+/// Asks a running session to shut down; cheap to clone, e.g. into a signal
+/// handler.
+#[derive(Clone, Debug)]
+pub struct ShutdownTrigger(Arc<watch::Sender<Stop>>);
+
+impl ShutdownTrigger {
+    /// Starts a graceful shutdown. Returns whether this call started it,
+    /// `false` if a shutdown was already under way.
+    pub fn shutdown(&self) -> bool {
+        self.0.send_if_modified(|stop| {
+            let running = *stop == Stop::Running;
+            if running {
+                *stop = Stop::Drain;
+            }
+            running
+        })
+    }
+
+    /// Shuts down without waiting for active connections to finish, also
+    /// cutting a graceful shutdown short.
+    pub fn shutdown_now(&self) {
+        self.0.send_replace(Stop::Now);
+    }
+}
+
+/// A running session, returned by [`run_session`] once the proxy accepts
+/// connections.
+///
+/// The session runs on its own task until ssh exits, a timeout fires or it
+/// is shut down. Dropping the handle shuts it down without draining
+/// connections; the pod is still deleted as long as the runtime keeps running.
+pub struct SessionHandle {
+    socks_port: u16,
+    pod_ref: PodRef,
+    trigger: ShutdownTrigger,
+    task: JoinHandle<SessionEnd>,
+}
+
+impl SessionHandle {
+    /// The local port of the proxy, also when `local_socks_port` was 0.
+    pub fn socks_port(&self) -> u16 {
+        self.socks_port
+    }
+
+    pub fn pod(&self) -> &PodRef {
+        &self.pod_ref
+    }
+
+    pub fn shutdown_trigger(&self) -> ShutdownTrigger {
+        self.trigger.clone()
+    }
+
+    /// Waits until the session has ended and its pod has been cleaned up.
+    pub async fn wait(self) -> anyhow::Result<SessionEnd> {
+        // The session takes a dropped trigger for a shutdown
+        let SessionHandle { trigger, task, .. } = self;
+        let end = task.await.map_err(|e| anyhow::anyhow!("The session task failed: {}", e));
+        drop(trigger);
+        end
+    }
+
+    /// Shuts the session down gracefully and waits until it has ended.
+    pub async fn shutdown(self) -> anyhow::Result<SessionEnd> {
+        self.trigger.shutdown();
+        self.wait().await
+    }
+}
+
+/// Deploys a pod for `config` and starts the proxy through it, with default
+/// [`SessionOptions`]. Only `context` is used; running one session per entry
+/// of `contexts` is up to the caller, see `Config::per_context`.
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// let config = k8socks_traits::config::Config {
+///     local_socks_port: Some(0),
+///     ..Default::default()
+/// };
+/// let session = k8socks_cli::run_session(config).await?;
+/// println!("SOCKS5 proxy on 127.0.0.1:{}", session.socks_port());
+/// session.shutdown().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn run_session(config: Config) -> anyhow::Result<SessionHandle> {
+    run_session_with(config, SessionOptions::default()).await
+}
+
+/// Like [`run_session`], with `options`.
+pub async fn run_session_with(mut config: Config, options: SessionOptions) -> anyhow::Result<SessionHandle> {
+    config.validate()?;
+    expand_paths(&mut config)?;
+    handle_orphaned_ssh(options.kill_orphans);
+
+    // Claim the proxy and health check ports before deploying: a busy port
+    // fails here, with no pod to clean up, and cannot be handed to the
+    // port-forward below.
+    let local_socks_port = config.local_socks_port.unwrap_or(1080);
+    let socks_listener = TcpListener::bind(("127.0.0.1", local_socks_port))
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to listen on port {}: {}", local_socks_port, e))?;
+    let health_listener = match config.health_port {
+        Some(port) => Some(
+            health::bind(port)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to serve health checks on port {}: {}", port, e))?,
+        ),
+        None => None,
+    };
+
+    let mut timings = PhaseTimings::default();
+    let k8s_service = timings.time("connect", K8sServiceImpl::new(&config)).await?;
+    let pod_ref = match options.session_name.as_deref() {
+        Some(name) => reuse_or_create(&k8s_service, &config, name, options.strict_quota, &mut timings).await?,
+        None => create_pod(&k8s_service, options.strict_quota, &mut timings).await?,
+    };
+    Span::current().record("pod", tracing::field::display(&pod_ref.name));
+
+    let proxy = start_proxy(&config, &options, &k8s_service, &pod_ref, socks_listener, &mut timings).await?;
+    info!("{}", timings.summary());
+    if let Some(addr) = health_listener.as_ref().and_then(|listener| listener.local_addr().ok()) {
+        info!("Serving health checks on http://127.0.0.1:{}/healthz", addr.port());
+    }
+
+    let (stop_tx, stop_rx) = watch::channel(Stop::Running);
+    let socks_port = proxy.relay.local_port();
+    let Proxy {
+        pf_handle,
+        ssh_service,
+        ssh_handle,
+        relay,
+    } = proxy;
+    let active = ActiveSession {
+        config,
+        options,
+        k8s_service,
+        pod_ref: pod_ref.clone(),
+        pf_handle,
+        ssh_service,
+        ssh_handle,
+        relay,
+        health_listener,
+    };
+    let task = tokio::spawn(active.serve(stop_rx).instrument(Span::current()));
+    Ok(SessionHandle {
+        socks_port,
+        pod_ref,
+        trigger: ShutdownTrigger(Arc::new(stop_tx)),
+        task,
+    })
+}
+
+/// The port-forward, ssh and relay of a session whose proxy is up.
+struct Proxy {
+    pf_handle: PortForwardHandle,
+    ssh_service: SshServiceImpl,
+    ssh_handle: SshProcessHandle,
+    relay: SocksRelay,
+}
+
+/// Waits for the pod and brings up the proxy through it on `socks_listener`.
+/// When that fails, the pod is cleaned up as after an ssh failure before the
+/// error is returned, so that a failed start leaves no pod behind unless it
+/// is kept on purpose.
+async fn start_proxy<K: K8sService>(
+    config: &Config,
+    options: &SessionOptions,
+    k8s_service: &K,
+    pod_ref: &PodRef,
+    socks_listener: TcpListener,
+    timings: &mut PhaseTimings,
+) -> anyhow::Result<Proxy> {
+    let proxy = connect_proxy(config, k8s_service, pod_ref, socks_listener, timings).await;
+    if let Err(e) = &proxy {
+        error!("Failed to start the proxy: {:#}", e);
+        clean_up_pod(config, options, k8s_service, pod_ref, SessionEnd::SshFailed).await;
+    }
+    proxy
+}
+
+async fn connect_proxy<K: K8sService>(
+    config: &Config,
+    k8s_service: &K,
+    pod_ref: &PodRef,
+    socks_listener: TcpListener,
+    timings: &mut PhaseTimings,
+) -> anyhow::Result<Proxy> {
+    // A reused pod is running already and passes right away
+    wait_until_ready(k8s_service, pod_ref, timings).await?;
+
+    // Start port forwarding and the SSH proxy
+    // Unless pinned, let the OS pick an ephemeral port for the SSH connection
+    let port_forward = k8s_service.port_forward(pod_ref, config.forwarded_local_port.unwrap_or(0));
+    let pf_handle = timings.time("port-forward", port_forward).await?;
+    info!("Established port-forward to pod on 127.0.0.1:{}", pf_handle.local_port);
+    let sshd = wait_for_sshd(
+        pf_handle.local_port,
+        config.ssh_predial_attempts.unwrap_or(10),
+        Duration::from_millis(config.ssh_predial_interval_millis.unwrap_or(200)),
+    );
+    timings.time("wait for sshd", sshd).await?;
+    // ssh serves SOCKS on an internal port; the relay owns the user-facing one
+    let ssh_config = Config {
+        local_socks_port: Some(0),
+        ..config.clone()
+    };
+    let ssh_service = SshServiceImpl::new(&ssh_config);
+    let ssh_handle = timings
+        .time("ssh handshake", ssh_service.start_socks_proxy(pf_handle.local_port))
+        .await?;
+    // `validate` has already rejected unknown proxy types
+    let proxy_type = config
+        .proxy_type
+        .as_deref()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(ProxyType::Socks5);
+    let relay = SocksRelay::from_listener(socks_listener, ssh_handle.socks_port, proxy_type, config.max_connections)?;
+    match proxy_type {
+        ProxyType::Socks5 => info!("SOCKS5 proxy is now running on 127.0.0.1:{}", relay.local_port()),
+        ProxyType::Http => info!("HTTP proxy is now running on 127.0.0.1:{}", relay.local_port()),
+    }
+    Ok(Proxy {
+        pf_handle,
+        ssh_service,
+        ssh_handle,
+        relay,
+    })
+}
+
+/// Warns about ssh processes that earlier runs left behind, which may still
+/// hold a SOCKS port, or terminates them with `kill`.
+fn handle_orphaned_ssh(kill: bool) {
+    let orphans = match find_orphaned_ssh() {
+        Ok(orphans) => orphans,
+        Err(e) => {
+            debug!("Skipping the check for orphaned ssh processes: {}", e);
+            return;
+        }
+    };
+    for orphan in orphans {
+        debug!("Orphaned ssh process {}: {}", orphan.pid, orphan.command);
+        if !kill {
+            warn!(
+                "ssh process {} on SOCKS port {} looks left behind by an earlier k8socks run",
+                orphan.pid, orphan.socks_port
+            );
+            warn!("Stop it with 'kill {}', or pass --kill-orphans to stop such processes on start", orphan.pid);
+            continue;
+        }
+        match terminate(orphan.pid) {
+            Ok(()) => info!("Terminated orphaned ssh process {} on SOCKS port {}", orphan.pid, orphan.socks_port),
+            Err(e) => warn!("Failed to terminate orphaned ssh process {}: {}", orphan.pid, e),
+        }
+    }
+}
+
+/// Expands `~` in the paths of `config`. Fails when a path starts with `~`
+/// but the home directory is unknown.
+pub fn expand_paths(config: &mut Config) -> anyhow::Result<()> {
+    let expand = |path: &str| {
+        ConfigServiceImpl::expand_tilde(path)
+            .map(|path| path.to_string_lossy().into_owned())
+            .ok_or_else(|| anyhow::anyhow!("Cannot expand '{}': the home directory is unknown", path))
+    };
+    if let Some(path) = config.kubeconfig.as_deref() {
+        config.kubeconfig = Some(expand(path)?);
+    }
+    if let Some(path) = config.ssh_public_key_path.as_deref() {
+        config.ssh_public_key_path = Some(expand(path)?);
+    }
+    if let Some(path) = config.ssh_config_file.as_deref() {
+        config.ssh_config_file = Some(expand(path)?);
+    }
+    if let Some(paths) = config.ssh_public_key_paths.as_mut() {
+        for path in paths.iter_mut() {
+            *path = expand(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// What a session holds on to once the proxy is up.
+struct ActiveSession {
+    config: Config,
+    options: SessionOptions,
+    k8s_service: K8sServiceImpl,
+    pod_ref: PodRef,
+    pf_handle: PortForwardHandle,
+    ssh_service: SshServiceImpl,
+    ssh_handle: SshProcessHandle,
+    relay: SocksRelay,
+    health_listener: Option<tokio::net::TcpListener>,
+}
+
+impl ActiveSession {
+    /// Serves the proxy until the session ends, then cleans up the pod.
+    async fn serve(self, mut stop: watch::Receiver<Stop>) -> SessionEnd {
+        let ActiveSession {
+            config,
+            options,
+            k8s_service,
+            pod_ref,
+            pf_handle,
+            ssh_service,
+            ssh_handle,
+            relay,
+            health_listener,
+        } = self;
+
+        // The session ends when ssh exits, on shutdown or when the optional
+        // session or idle timeout fires
+        let session_timeout = async {
+            match config.session_timeout_seconds {
+                Some(secs) => tokio::time::sleep(Duration::from_secs(secs)).await,
+                None => std::future::pending().await,
+            }
+        };
+        let tracker = relay.tracker();
+        let idle_timeout = async {
+            match config.idle_timeout_seconds {
+                Some(secs) => tracker.wait_idle_for(Duration::from_secs(secs)).await,
+                None => std::future::pending().await,
+            }
+        };
+        // Stops serving together with the loop below
+        let health_server = async {
+            match health_listener {
+                Some(listener) => health::serve(listener, || relay.is_healthy()).await,
+                None => std::future::pending().await,
+            }
+        };
+        let throughput_logger = async {
+            match config.log_throughput {
+                Some(true) => {
+                    let interval = Duration::from_secs(config.log_throughput_interval_seconds.unwrap_or(5));
+                    log_throughput(pf_handle.stats(), interval).await
+                }
+                _ => std::future::pending().await,
+            }
+        };
+        let (stop_ssh, stop_ssh_rx) = oneshot::channel();
+        let mut watch = ssh_service.watch(ssh_handle, stop_ssh_rx);
+        // A dropped handle counts as a shutdown too
+        let end = tokio::select! {
+            res = &mut watch => match res {
+                Ok(()) => SessionEnd::SshExited,
+                Err(e) => {
+                    error!("SSH process failed: {}", e);
+                    SessionEnd::SshFailed
+                }
+            },
+            _ = stop.wait_for(|stop| *stop != Stop::Running) => {
+                warn!("Shutting down. Cleaning up...");
+                SessionEnd::Shutdown
+            }
+            _ = session_timeout => {
+                warn!("Session timeout reached. Cleaning up...");
+                SessionEnd::SessionTimeout
+            }
+            _ = idle_timeout => {
+                warn!(
+                    "Idle timeout reached: no proxy connections for {}s. Cleaning up...",
+                    config.idle_timeout_seconds.unwrap_or_default()
+                );
+                SessionEnd::IdleTimeout
+            }
+            _ = health_server => unreachable!("the health check server runs until it is dropped"),
+            _ = throughput_logger => unreachable!("the throughput logger runs until it is dropped"),
+        };
+        let signalled = !matches!(end, SessionEnd::SshExited | SessionEnd::SshFailed);
+        let mut ssh_running = signalled;
+
+        if signalled {
+            let drain_timeout = config.drain_timeout_seconds.unwrap_or(0);
+            let active = relay.tracker().active();
+            if drain_timeout > 0 && active > 0 {
+                info!("Waiting up to {}s for {} active connection(s) to finish...", drain_timeout, active);
+                tokio::select! {
+                    drained = relay.drain(Duration::from_secs(drain_timeout)) => {
+                        if !drained {
+                            let remaining = relay.tracker().active();
+                            warn!("Drain timeout reached; closing {} remaining connection(s)", remaining);
+                        }
+                    }
+                    _ = &mut watch => ssh_running = false,
+                    _ = stop.wait_for(|stop| *stop == Stop::Now) => warn!("Closing connections now"),
+                }
+            }
+        }
+
+        // Stop ssh and wait for it before the pod goes away, so that its
+        // connection is closed rather than reset under it
+        drop(relay);
+        if ssh_running {
+            let _ = stop_ssh.send(());
+            if let Err(e) = watch.await {
+                debug!("SSH process failed while stopping: {}", e);
+            }
+        }
+
+        clean_up_pod(&config, &options, &k8s_service, &pod_ref, end).await;
+        info!("Shutdown complete.");
+        end
+    }
+}
+
+/// Deletes the pod once a session has ended the way `end` says, or keeps it:
+/// after an ssh failure with `keep_on_error`, for a named session, and with
+/// `delete_pod_on_exit` off.
+async fn clean_up_pod<K: K8sService>(
+    config: &Config,
+    options: &SessionOptions,
+    k8s_service: &K,
+    pod_ref: &PodRef,
+    end: SessionEnd,
+) {
+    if end == SessionEnd::SshFailed && options.keep_on_error {
+        let (name, namespace) = (&pod_ref.name, &pod_ref.namespace);
+        warn!("Keeping pod '{}' in namespace '{}' for inspection.", name, namespace);
+        info!("Inspect it with: kubectl logs -n {} {}", namespace, name);
+        info!("             or: kubectl exec -it -n {} {} -- sh", namespace, name);
+        info!("Remove it afterwards with 'k8socks cleanup' or: kubectl delete pod -n {} {}", namespace, name);
+    } else if let Some(session) = &options.session_name {
+        let (name, namespace) = (&pod_ref.name, &pod_ref.namespace);
+        info!("Keeping pod '{}' in namespace '{}' for session '{}'.", name, namespace, session);
+        info!("Reuse it with: k8socks --session-name {} deploy", session);
+        info!("Remove it with 'k8socks cleanup' or: kubectl delete pod -n {} {}", namespace, name);
+    } else if !config.delete_pod_on_exit.unwrap_or(true) {
+        let (name, namespace) = (&pod_ref.name, &pod_ref.namespace);
+        let port = ssh_container_port(config);
+        info!("Keeping pod '{}' in namespace '{}'; it stops once its TTL has passed.", name, namespace);
+        info!("Reconnect with: kubectl port-forward -n {} {} {}:{}", namespace, name, port, port);
+        let ssh_command = build_ssh_command(config, config.local_socks_port.unwrap_or(1080), port);
+        info!("           and: {}", render_command(&ssh_command));
+        info!("Remove it with 'k8socks cleanup' or: kubectl delete pod -n {} {}", namespace, name);
+    } else {
+        // Shutdowns often come in waves, e.g. when a whole fleet is stopped
+        if !matches!(end, SessionEnd::SshExited | SessionEnd::SshFailed) {
+            let jitter = random_delay(Duration::from_millis(config.cleanup_jitter_millis.unwrap_or(500)));
+            debug!("Waiting {}ms before deleting the pod", jitter.as_millis());
+            tokio::time::sleep(jitter).await;
+        }
+        info!("Cleaning up pod...");
+        let policy = BackoffPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(2),
+            jitter: 0.5,
+        };
+        let delete_grace_period = options.delete_grace_period;
+        // A 403 or 404 answers the same way again; only transient errors are retried.
+        let delete = retry_with_backoff_if(&policy, is_retryable, |attempt| async move {
+            k8s_service.delete_pod(pod_ref, delete_grace_period).await.inspect_err(|e| {
+                debug!("Deleting pod '{}' failed (attempt {}): {}", pod_ref.name, attempt, e)
+            })
+        });
+        match delete.await {
+            Ok(()) if options.wait_for_delete => {
+                let timeout = delete_wait_timeout(config, delete_grace_period);
+                if let Err(e) = k8s_service.wait_for_pod_deleted(pod_ref, timeout).await {
+                    error!("{}", e);
+                }
+            }
+            Ok(()) => {}
+            Err(e) => error!("Failed to delete pod on exit: {}", e),
+        }
+    }
+}
+
+/// Logs the byte rates of the port-forward every `interval`, until dropped.
+async fn log_throughput(stats: &TransferStats, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ticker.tick().await;
+    let mut previous = stats.snapshot();
+    let mut sampled_at = tokio::time::Instant::now();
+    loop {
+        ticker.tick().await;
+        let current = stats.snapshot();
+        for line in throughput_lines(&previous, &current, sampled_at.elapsed()) {
+            debug!("Throughput {}", line);
+        }
+        previous = current;
+        sampled_at = tokio::time::Instant::now();
+    }
+}
+
+/// Joins a command line for display, single-quoting arguments that contain
+/// whitespace or quotes.
+pub fn render_command(command: &[String]) -> String {
+    command
+        .iter()
+        .map(|arg| {
+            if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '\'' || c == '"') {
+                format!("'{}'", arg.replace('\'', r"'\''"))
+            } else {
+                arg.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// How long `--wait` waits for a deleted pod to be gone: its termination grace
+/// period plus some time for the kubelet to report back.
+pub fn delete_wait_timeout(config: &Config, delete_grace_period: Option<u32>) -> Duration {
+    let grace = delete_grace_period
+        .map(i64::from)
+        .or(config.pod_termination_grace_period_seconds)
+        .unwrap_or(30);
+    Duration::from_secs(grace.max(0) as u64 + 30)
+}
+
+/// Reuses the pod recorded for session `name` while it is running in the
+/// same context, and creates and records a new one otherwise, without
+/// waiting for it.
+async fn reuse_or_create<K: K8sService>(
+    k8s_service: &K,
+    config: &Config,
+    name: &str,
+    strict_quota: bool,
+    timings: &mut PhaseTimings,
+) -> anyhow::Result<PodRef> {
+    let store = SessionStore::in_home_dir()
+        .ok_or_else(|| anyhow::anyhow!("Cannot record session '{}': the home directory is unknown", name))?;
+    if let Some(record) = store.load(name).filter(|record| record.context == config.context) {
+        let pod = k8s_service.get_pod(&record.pod_ref()).await?;
+        match pod {
+            Some(pod_ref) if !is_stale(Some(&pod_ref)) => {
+                info!("Reusing pod '{}' of session '{}'.", pod_ref.name, name);
+                return Ok(pod_ref);
+            }
+            _ => info!("Pod '{}' of session '{}' is no longer running; deploying a new one.", record.pod, name),
+        }
+    }
+    let pod_ref = create_pod(k8s_service, strict_quota, timings).await?;
+    record_session(&store, name, config, &pod_ref);
+    Ok(pod_ref)
+}
+
+pub fn record_session(store: &SessionStore, name: &str, config: &Config, pod_ref: &PodRef) {
+    let record = SessionRecord {
+        name: name.to_string(),
+        pod: pod_ref.name.clone(),
+        namespace: pod_ref.namespace.clone(),
+        context: config.context.clone(),
+    };
+    if let Err(e) = store.save(&record) {
+        warn!("Failed to record session '{}': {}", name, e);
+    }
+}
+
+/// Warns about resource quotas the pod would exceed, and fails on them with
+/// `strict_quota`. The API server rejects a pod over quota with a terse
+/// message; this says which quota and resource it is up front.
+pub async fn check_quota_before_deploy<K: K8sService>(k8s_service: &K, strict_quota: bool) -> anyhow::Result<()> {
+    match k8s_service.check_quota().await {
+        Ok(problems) => {
+            for problem in &problems {
+                warn!("{}", problem);
+            }
+            if strict_quota && !problems.is_empty() {
+                return Err(K8sError::QuotaExceeded.into());
+            }
+        }
+        Err(e) => debug!("Skipping the resource quota check: {}", e),
+    }
+    Ok(())
+}
+
+/// Checks the resource quotas and creates the pod, without waiting for it.
+pub async fn create_pod<K: K8sService>(
+    k8s_service: &K,
+    strict_quota: bool,
+    timings: &mut PhaseTimings,
+) -> anyhow::Result<PodRef> {
+    check_quota_before_deploy(k8s_service, strict_quota).await?;
+    info!("Deploying SSH server pod...");
+    let pod_ref = timings.time("create pod", k8s_service.deploy_pod()).await?;
+    info!("Pod '{}' created in namespace '{}'.", pod_ref.name, pod_ref.namespace);
+    Ok(pod_ref)
+}
+
+/// Waits until the pod is ready, logging why it is not when waiting fails.
+pub async fn wait_until_ready<K: K8sService>(
+    k8s_service: &K,
+    pod_ref: &PodRef,
+    timings: &mut PhaseTimings,
+) -> anyhow::Result<()> {
+    info!("Waiting for pod '{}' to be ready...", pod_ref.name);
+    // Includes pulling the image on nodes that do not have it yet.
+    if let Err(e) = timings.time("wait for ready", k8s_service.wait_for_pod_ready(pod_ref)).await {
+        // Events usually say why, e.g. "0/3 nodes are available: insufficient cpu".
+        match k8s_service.get_pod_events(pod_ref).await {
+            Ok(events) => {
+                for message in pod_problem_messages(&events, 5) {
+                    error!("Pod '{}': {}", pod_ref.name, message);
+                }
+            }
+            Err(events_err) => debug!("Failed to fetch events for pod '{}': {}", pod_ref.name, events_err),
+        }
+        return Err(e.into());
+    }
+    info!("Pod is running and ready.");
+    match k8s_service.get_pod_node(pod_ref).await {
+        Ok(Some(node)) => info!("Pod scheduled on node {}", node),
+        Ok(None) => debug!("Pod '{}' is not scheduled on a node yet", pod_ref.name),
+        Err(e) => debug!("Failed to look up the node of pod '{}': {}", pod_ref.name, e),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_k8s::FakeK8sService;
+
+    /// A session that ends like `ActiveSession::serve`: when ssh exits, or on
+    /// a shutdown request or once every trigger is dropped.
+    fn fake_session(ssh_exit: oneshot::Receiver<()>) -> SessionHandle {
+        let (stop_tx, mut stop_rx) = watch::channel(Stop::Running);
+        let task = tokio::spawn(async move {
+            tokio::select! {
+                _ = ssh_exit => SessionEnd::SshExited,
+                _ = stop_rx.wait_for(|stop| *stop != Stop::Running) => SessionEnd::Shutdown,
+            }
+        });
+        session_handle(stop_tx, task)
+    }
+
+    fn session_handle(stop_tx: watch::Sender<Stop>, task: JoinHandle<SessionEnd>) -> SessionHandle {
+        SessionHandle {
+            socks_port: 1080,
+            pod_ref: PodRef {
+                name: "k8socks-test123".to_string(),
+                namespace: "default".to_string(),
+                creation_timestamp: None,
+                phase: None,
+                node_name: None,
+            },
+            trigger: ShutdownTrigger(Arc::new(stop_tx)),
+            task,
+        }
+    }
+
+    #[test]
+    fn test_shutdown_trigger() {
+        let (stop_tx, stop_rx) = watch::channel(Stop::Running);
+        let trigger = ShutdownTrigger(Arc::new(stop_tx));
+
+        assert!(trigger.clone().shutdown());
+        assert_eq!(*stop_rx.borrow(), Stop::Drain);
+        // Only the first request starts the shutdown.
+        assert!(!trigger.shutdown());
+
+        trigger.shutdown_now();
+        assert_eq!(*stop_rx.borrow(), Stop::Now);
+        assert!(!trigger.shutdown());
+        assert_eq!(*stop_rx.borrow(), Stop::Now);
+    }
+
+    #[tokio::test]
+    async fn test_wait_keeps_the_session_running() {
+        let (ssh_exit, ssh_exit_rx) = oneshot::channel();
+        let wait = tokio::spawn(fake_session(ssh_exit_rx).wait());
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!wait.is_finished());
+
+        ssh_exit.send(()).unwrap();
+        assert_eq!(wait.await.unwrap().unwrap(), SessionEnd::SshExited);
+    }
+
+    #[tokio::test]
+    async fn test_trigger_ends_a_waited_session() {
+        let (_ssh_exit, ssh_exit_rx) = oneshot::channel();
+        let session = fake_session(ssh_exit_rx);
+        let trigger = session.shutdown_trigger();
+        let wait = tokio::spawn(session.wait());
+
+        assert!(trigger.shutdown());
+        assert_eq!(wait.await.unwrap().unwrap(), SessionEnd::Shutdown);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let (_ssh_exit, ssh_exit_rx) = oneshot::channel();
+        let session = fake_session(ssh_exit_rx);
+        assert_eq!(session.socks_port(), 1080);
+        assert_eq!(session.pod().name, "k8socks-test123");
+        assert_eq!(session.shutdown().await.unwrap(), SessionEnd::Shutdown);
+    }
+
+    #[tokio::test]
+    async fn test_wait_reports_a_failed_task() {
+        let (stop_tx, _) = watch::channel(Stop::Running);
+        let session = session_handle(stop_tx, tokio::spawn(async { panic!("the session task panicked") }));
+        let err = session.wait().await.unwrap_err();
+        assert!(err.to_string().starts_with("The session task failed: "), "{}", err);
+    }
+
+    /// Starts a proxy through a fake pod whose sshd never answers.
+    async fn start_without_sshd(options: &SessionOptions) -> (FakeK8sService, PodRef) {
+        let k8s_service = FakeK8sService::default();
+        let config = Config {
+            ssh_predial_attempts: Some(1),
+            ssh_predial_interval_millis: Some(10),
+            ..Default::default()
+        };
+        let pod_ref = k8s_service.deploy_pod().await.unwrap();
+        let socks_listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+
+        let mut timings = PhaseTimings::default();
+        let started = start_proxy(&config, options, &k8s_service, &pod_ref, socks_listener, &mut timings).await;
+        let err = started.err().expect("sshd is unreachable");
+        assert!(matches!(err.downcast_ref(), Some(K8sError::SshdUnreachable(_))), "{}", err);
+        (k8s_service, pod_ref)
+    }
+
+    #[tokio::test]
+    async fn test_failed_start_deletes_the_pod() {
+        let (k8s_service, pod_ref) = start_without_sshd(&SessionOptions::default()).await;
+        assert_eq!(*k8s_service.deleted.lock().unwrap(), [pod_ref.name]);
+        assert!(k8s_service.pods.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_start_keeps_pods_kept_on_purpose() {
+        let keep_on_error = SessionOptions {
+            keep_on_error: true,
+            ..Default::default()
+        };
+        let named = SessionOptions {
+            session_name: Some("work".to_string()),
+            ..Default::default()
+        };
+        for options in [keep_on_error, named] {
+            let (k8s_service, pod_ref) = start_without_sshd(&options).await;
+            assert!(k8s_service.deleted.lock().unwrap().is_empty(), "{:?}", options);
+            assert_eq!(*k8s_service.pods.lock().unwrap(), [pod_ref.name]);
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::signal;
+use tokio::sync::oneshot;
+use tracing::warn;
+
+use k8socks_traits::config::Config;
+use k8socks_traits::k8s::{K8sService, PodRef};
+use k8socks_traits::ssh::SshService;
+
+use k8socks_cli::session::{create_pod, wait_until_ready};
+use k8socks_cli::timings::PhaseTimings;
+use k8socks_k8s::{wait_for_sshd, K8sServiceImpl};
+use k8socks_ssh::relay::{ProxyType, SocksRelay};
+use k8socks_ssh::{socks5_connect, SshServiceImpl};
+
+/// A plain-HTTP service that answers with the caller's public IP, i.e. the
+/// egress address of the cluster when fetched through the proxy.
+pub const DEFAULT_TEST_URL: &str = "http://checkip.amazonaws.com/";
+
+/// How long ssh gets to serve SOCKS once it has been started.
+const PROXY_TIMEOUT: Duration = Duration::from_secs(30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The name of the phase recorded when Ctrl+C cut the test short.
+pub const INTERRUPTED_PHASE: &str = "interrupted";
+
+/// The outcome of one phase of the smoke test.
+pub struct PhaseResult {
+    pub name: &'static str,
+    /// `PASS`, `FAIL` or `SKIP`.
+    pub status: &'static str,
+    pub elapsed: Duration,
+    pub detail: String,
+}
+
+#[derive(Default)]
+struct Report {
+    phases: Vec<PhaseResult>,
+}
+
+impl Report {
+    /// Runs and times a phase that yields a value and a detail line.
+    async fn run<T>(
+        &mut self,
+        name: &'static str,
+        phase: impl Future<Output = anyhow::Result<(T, String)>>,
+    ) -> Option<T> {
+        let start = Instant::now();
+        let result = phase.await;
+        let elapsed = start.elapsed();
+        let (status, detail, value) = match result {
+            Ok((value, detail)) => ("PASS", detail, Some(value)),
+            Err(e) => ("FAIL", format!("{:#}", e), None),
+        };
+        self.phases.push(PhaseResult {
+            name,
+            status,
+            elapsed,
+            detail,
+        });
+        value
+    }
+
+    fn skip(&mut self, name: &'static str, detail: &str) {
+        self.phases.push(PhaseResult {
+            name,
+            status: "SKIP",
+            elapsed: Duration::ZERO,
+            detail: detail.to_string(),
+        });
+    }
+}
+
+/// Splits an `http://host[:port][/path]` URL into host, port and path.
+pub fn parse_http_url(url: &str) -> anyhow::Result<(String, u16, String)> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow::anyhow!("only http:// URLs are supported, got '{}'", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port.parse().map_err(|_| anyhow::anyhow!("invalid port in '{}'", url))?;
+            (host, port)
+        }
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        anyhow::bail!("missing host in '{}'", url);
+    }
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+/// Fetches `url` through the SOCKS proxy on `socks_port` and describes the
+/// response, e.g. `HTTP/1.1 200 OK: 203.0.113.7`.
+async fn http_get(socks_port: u16, url: &str) -> anyhow::Result<String> {
+    let (host, port, path) = parse_http_url(url)?;
+    let mut stream = TcpStream::connect(("127.0.0.1", socks_port)).await?;
+    socks5_connect(&mut stream, &host, port).await?;
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: k8socks-test\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    describe_response(&host, &String::from_utf8_lossy(&response))
+}
+
+/// Describes a raw HTTP response from `host` by its status line and the first
+/// line of its body. Statuses outside 2xx and 3xx are errors.
+fn describe_response(host: &str, response: &str) -> anyhow::Result<String> {
+    let status_line = response.lines().next().unwrap_or_default();
+    let code = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| anyhow::anyhow!("not an HTTP response: '{}'", status_line))?;
+    if !(200..400).contains(&code) {
+        anyhow::bail!("{} answered '{}'", host, status_line);
+    }
+    let body = response.split_once("\r\n\r\n").map(|(_, body)| body.trim()).unwrap_or_default();
+    match body.lines().next() {
+        Some(first) => Ok(format!("{}: {:.60}", status_line, first)),
+        None => Ok(status_line.to_string()),
+    }
+}
+
+/// Deploys a pod, brings up the proxy, fetches `test_url` through it (unless
+/// `None`) and deletes the pod again. Phases after a failed one are not run,
+/// but a created pod is always deleted.
+pub async fn run(config: &Config, test_url: Option<&str>, delete_grace_period: Option<u32>) -> Vec<PhaseResult> {
+    let mut report = Report::default();
+    // A free port, so the test does not clash with a proxy already running.
+    let config = Config {
+        local_socks_port: Some(0),
+        ..config.clone()
+    };
+
+    let connect = async {
+        let k8s_service = K8sServiceImpl::new(&config).await?;
+        Ok::<_, anyhow::Error>((k8s_service, "cluster reachable".to_string()))
+    };
+    let Some(k8s_service) = report.run("connect", connect).await else {
+        return report.phases;
+    };
+    run_with_pod(&mut report, &config, &k8s_service, test_url, delete_grace_period).await;
+    report.phases
+}
+
+/// Runs the phases from creating the pod on, and the `cleanup` phase once the
+/// pod has been created, also when Ctrl+C cuts a phase short.
+async fn run_with_pod<K: K8sService>(
+    report: &mut Report,
+    config: &Config,
+    k8s_service: &K,
+    test_url: Option<&str>,
+    delete_grace_period: Option<u32>,
+) {
+    let mut created = None;
+    let interrupted = tokio::select! {
+        _ = pod_phases(report, config, k8s_service, test_url, &mut created) => false,
+        _ = signal::ctrl_c() => {
+            warn!("Interrupted. Cleaning up...");
+            true
+        }
+    };
+    if interrupted {
+        report.phases.push(PhaseResult {
+            name: INTERRUPTED_PHASE,
+            status: "FAIL",
+            elapsed: Duration::ZERO,
+            detail: "interrupted by Ctrl+C".to_string(),
+        });
+    }
+
+    let Some(pod_ref) = created else {
+        return;
+    };
+    let cleanup = async {
+        k8s_service.delete_pod(&pod_ref, delete_grace_period).await?;
+        Ok::<_, anyhow::Error>(((), "pod deleted".to_string()))
+    };
+    report.run("cleanup", cleanup).await;
+}
+
+/// Creates the pod, storing it in `created` right away so that it is cleaned
+/// up whatever happens next, then waits for it and tests the proxy.
+async fn pod_phases<K: K8sService>(
+    report: &mut Report,
+    config: &Config,
+    k8s_service: &K,
+    test_url: Option<&str>,
+    created: &mut Option<PodRef>,
+) {
+    // The report times the phases itself.
+    let mut timings = PhaseTimings::default();
+    let deploy = async {
+        let pod_ref = create_pod(k8s_service, false, &mut timings).await?;
+        let detail = format!("pod '{}' in namespace '{}'", pod_ref.name, pod_ref.namespace);
+        Ok::<_, anyhow::Error>((pod_ref, detail))
+    };
+    let Some(pod_ref) = report.run("deploy", deploy).await else {
+        return;
+    };
+    *created = Some(pod_ref.clone());
+
+    let ready = async {
+        wait_until_ready(k8s_service, &pod_ref, &mut timings).await?;
+        Ok::<_, anyhow::Error>(((), "pod running and ready".to_string()))
+    };
+    if report.run("ready", ready).await.is_none() {
+        return;
+    }
+    proxy_phases(report, config, k8s_service, &pod_ref, test_url).await;
+}
+
+async fn proxy_phases<K: K8sService>(
+    report: &mut Report,
+    config: &Config,
+    k8s_service: &K,
+    pod_ref: &PodRef,
+    test_url: Option<&str>,
+) {
+    let forward = async {
+        let pf_handle = k8s_service
+            .port_forward(pod_ref, config.forwarded_local_port.unwrap_or(0))
+            .await?;
+        wait_for_sshd(
+            pf_handle.local_port,
+            config.ssh_predial_attempts.unwrap_or(10),
+            Duration::from_millis(config.ssh_predial_interval_millis.unwrap_or(200)),
+        )
+        .await?;
+        let detail = format!("sshd answers on 127.0.0.1:{}", pf_handle.local_port);
+        Ok::<_, anyhow::Error>((pf_handle, detail))
+    };
+    let Some(pf_handle) = report.run("port-forward", forward).await else {
+        return;
+    };
+
+    let ssh_service = SshServiceImpl::new(config);
+    let proxy = async {
+        let ssh_handle = ssh_service.start_socks_proxy(pf_handle.local_port).await?;
+        let relay = SocksRelay::bind(0, ssh_handle.socks_port, ProxyType::Socks5, None).await?;
+        // Never stopped; dropping `watch` kills ssh.
+        let mut watch = ssh_service.watch(ssh_handle, oneshot::channel().1);
+        let handshake = async {
+            let deadline = Instant::now() + PROXY_TIMEOUT;
+            while !relay.is_healthy().await {
+                if Instant::now() >= deadline {
+                    return false;
+                }
+                tokio::time::sleep(Duration::from_millis(250)).await;
+            }
+            true
+        };
+        tokio::select! {
+            res = &mut watch => {
+                res?;
+                anyhow::bail!("ssh exited before the proxy was up");
+            }
+            up = handshake => if !up {
+                anyhow::bail!("no SOCKS handshake within {}s", PROXY_TIMEOUT.as_secs());
+            }
+        }
+        let detail = format!("SOCKS5 on 127.0.0.1:{}", relay.local_port());
+        Ok::<_, anyhow::Error>(((relay, watch), detail))
+    };
+    // Dropping `watch` at the end kills the ssh process.
+    let Some((relay, mut watch)) = report.run("proxy", proxy).await else {
+        return;
+    };
+
+    let Some(url) = test_url else {
+        report.skip("request", "skipped (--skip-request)");
+        return;
+    };
+    let request = async {
+        tokio::select! {
+            res = &mut watch => {
+                res?;
+                anyhow::bail!("ssh exited during the request");
+            }
+            response = tokio::time::timeout(REQUEST_TIMEOUT, http_get(relay.local_port(), url)) => {
+                let response = response
+                    .map_err(|_| anyhow::anyhow!("no response within {}s", REQUEST_TIMEOUT.as_secs()))??;
+                Ok::<_, anyhow::Error>(((), format!("{} -> {}", url, response)))
+            }
+        }
+    };
+    report.run("request", request).await;
+}
+
+/// Prints one line per phase and an overall verdict. Returns whether every
+/// phase passed or was skipped.
+pub fn print_report(phases: &[PhaseResult]) -> bool {
+    let (report, passed) = format_report(phases);
+    print!("{}", report);
+    passed
+}
+
+fn format_report(phases: &[PhaseResult]) -> (String, bool) {
+    let mut report = String::new();
+    for phase in phases {
+        report += &format!(
+            "{:<5} {:<13} {:>6.1}s  {}\n",
+            phase.status,
+            phase.name,
+            phase.elapsed.as_secs_f64(),
+            phase.detail
+        );
+    }
+    let passed = phases.iter().all(|phase| phase.status != "FAIL");
+    let total: Duration = phases.iter().map(|phase| phase.elapsed).sum();
+    let verdict = if passed { "PASS" } else { "FAIL" };
+    report += &format!("{:<5} {:<13} {:>6.1}s\n", verdict, "total", total.as_secs_f64());
+    (report, passed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_k8s::FakeK8sService;
+
+    fn phase(name: &'static str, status: &'static str, millis: u64, detail: &str) -> PhaseResult {
+        PhaseResult {
+            name,
+            status,
+            elapsed: Duration::from_millis(millis),
+            detail: detail.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_http_url() {
+        assert_eq!(
+            parse_http_url(DEFAULT_TEST_URL).unwrap(),
+            ("checkip.amazonaws.com".to_string(), 80, "/".to_string())
+        );
+        assert_eq!(
+            parse_http_url("http://10.0.0.5:8080/healthz?full=1").unwrap(),
+            ("10.0.0.5".to_string(), 8080, "/healthz?full=1".to_string())
+        );
+        assert_eq!(
+            parse_http_url("http://example.com").unwrap(),
+            ("example.com".to_string(), 80, "/".to_string())
+        );
+        for url in ["https://example.com/", "example.com", "http://example.com:http/", "http:///path"] {
+            assert!(parse_http_url(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_describe_response() {
+        let ok = "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\n203.0.113.7\n";
+        assert_eq!(describe_response("checkip", ok).unwrap(), "HTTP/1.1 200 OK: 203.0.113.7");
+
+        let redirect = "HTTP/1.1 301 Moved Permanently\r\nLocation: https://example.com/\r\n\r\n";
+        assert_eq!(describe_response("example.com", redirect).unwrap(), "HTTP/1.1 301 Moved Permanently");
+
+        // Only the first 60 characters of the body are shown.
+        let long = format!("HTTP/1.1 200 OK\r\n\r\n{}", "x".repeat(100));
+        assert_eq!(describe_response("example.com", &long).unwrap(), format!("HTTP/1.1 200 OK: {}", "x".repeat(60)));
+
+        let err = describe_response("example.com", "HTTP/1.1 403 Forbidden\r\n\r\ndenied").unwrap_err();
+        assert_eq!(err.to_string(), "example.com answered 'HTTP/1.1 403 Forbidden'");
+        let err = describe_response("example.com", "SSH-2.0-OpenSSH_9.6\r\n").unwrap_err();
+        assert_eq!(err.to_string(), "not an HTTP response: 'SSH-2.0-OpenSSH_9.6'");
+        assert!(describe_response("example.com", "").is_err());
+    }
+
+    #[test]
+    fn test_format_report() {
+        let phases = [
+            phase("connect", "PASS", 200, "cluster reachable"),
+            phase("deploy", "PASS", 12_300, "pod 'k8socks-abc' in namespace 'default'"),
+            phase("request", "SKIP", 0, "skipped (--skip-request)"),
+        ];
+        let (report, passed) = format_report(&phases);
+        assert!(passed);
+        assert_eq!(
+            report,
+            "PASS  connect          0.2s  cluster reachable\n\
+             PASS  deploy          12.3s  pod 'k8socks-abc' in namespace 'default'\n\
+             SKIP  request          0.0s  skipped (--skip-request)\n\
+             PASS  total           12.5s\n"
+        );
+
+        let phases = [
+            phase("connect", "PASS", 100, "cluster reachable"),
+            phase("deploy", "FAIL", 60_000, "Pod did not become ready"),
+        ];
+        let (report, passed) = format_report(&phases);
+        assert!(!passed);
+        assert!(report.ends_with("FAIL  total           60.1s\n"), "{}", report);
+    }
+
+    #[tokio::test]
+    async fn test_pod_deleted_when_not_ready() {
+        let k8s_service = FakeK8sService {
+            never_ready: true,
+            ..Default::default()
+        };
+        let mut report = Report::default();
+        run_with_pod(&mut report, &Config::default(), &k8s_service, None, None).await;
+
+        let phases: Vec<_> = report.phases.iter().map(|phase| (phase.name, phase.status)).collect();
+        assert_eq!(phases, [("deploy", "PASS"), ("ready", "FAIL"), ("cleanup", "PASS")]);
+        assert_eq!(*k8s_service.deleted.lock().unwrap(), ["k8socks-fake0"]);
+        assert!(k8s_service.pods.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+//! How long each phase of starting a session takes, e.g. to tell a slow
+//! image pull from a slow sshd.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// The phases of a session start in the order they first ran, each with the
+/// time spent in it.
+#[derive(Debug, Default)]
+pub struct PhaseTimings {
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl PhaseTimings {
+    /// Runs and times `phase`, whether it succeeds or not.
+    pub async fn time<T>(&mut self, name: &'static str, phase: impl Future<Output = T>) -> T {
+        let start = Instant::now();
+        let output = phase.await;
+        self.record(name, start.elapsed());
+        output
+    }
+
+    /// Adds `elapsed` to the phase `name`; a phase that runs again, e.g. on a
+    /// retry, accumulates.
+    pub fn record(&mut self, name: &'static str, elapsed: Duration) {
+        debug!("Phase '{}' took {:.2}s", name, elapsed.as_secs_f64());
+        match self.phases.iter_mut().find(|(phase, _)| *phase == name) {
+            Some((_, total)) => *total += elapsed,
+            None => self.phases.push((name, elapsed)),
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, elapsed)| *elapsed).sum()
+    }
+
+    /// One line for the log, e.g. `Started in 31.4s: connect 0.2s, create pod 0.3s, ...`.
+    pub fn summary(&self) -> String {
+        let phases: Vec<String> = self
+            .phases
+            .iter()
+            .map(|(name, elapsed)| format!("{} {:.1}s", name, elapsed.as_secs_f64()))
+            .collect();
+        format!("Started in {:.1}s: {}", self.total().as_secs_f64(), phases.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phase_timings() {
+        let mut timings = PhaseTimings::default();
+        timings.record("connect", Duration::from_millis(200));
+        timings.record("wait for ready", Duration::from_millis(30_000));
+        timings.record("connect", Duration::from_millis(100));
+
+        assert_eq!(timings.total(), Duration::from_millis(30_300));
+        // Phases keep the order in which they first ran, and repeats add up.
+        assert_eq!(timings.summary(), "Started in 30.3s: connect 0.3s, wait for ready 30.0s");
+    }
+
+    #[tokio::test]
+    async fn test_time_records_failed_phases() {
+        let mut timings = PhaseTimings::default();
+        let result: Result<(), &str> = timings.time("create pod", async { Err("forbidden") }).await;
+        assert_eq!(result, Err("forbidden"));
+        assert!(timings.summary().contains("create pod 0.0s"), "{}", timings.summary());
+    }
+}
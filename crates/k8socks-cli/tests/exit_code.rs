@@ -0,0 +1,34 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// An empty working and home directory, so that no real config file is read.
+fn sandbox(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("k8socks-exit-code-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn k8socks(dir: &Path, args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_k8socks-cli"))
+        .args(args)
+        .current_dir(dir)
+        .env("HOME", dir)
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+#[test]
+fn test_exit_code_for_config_errors() {
+    let dir = sandbox("config");
+    assert_eq!(k8socks(&dir, &["--print-config", "status"]), Some(0));
+
+    // Invalid values, unparsable files and unknown flags are all config errors.
+    assert_eq!(k8socks(&dir, &["--namespace", "Not_A_Label", "--print-config", "status"]), Some(2));
+    std::fs::write(dir.join("config.json"), "{ \"namespace\": ").unwrap();
+    assert_eq!(k8socks(&dir, &["--print-config", "status"]), Some(2));
+    assert_eq!(k8socks(&dir, &["--no-such-flag", "status"]), Some(2));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
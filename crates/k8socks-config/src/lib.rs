@@ -1,3 +1,5 @@
+pub mod sessions;
+
 use std::fs;
 use std::path::{Path, PathBuf};
 use directories::BaseDirs;
@@ -7,32 +9,31 @@ pub struct ConfigServiceImpl;
 
 impl ConfigService for ConfigServiceImpl {
     fn load_from_paths() -> Result<Config, ConfigError> {
-        let home_dir_path = BaseDirs::new().map(|dirs| {
-            dirs.home_dir().join(".k8socks/config.json")
-        });
-
-        let current_dir_path = Path::new("./config.json").to_path_buf();
+        let home_dir = BaseDirs::new().map(|dirs| dirs.home_dir().join(".k8socks"));
+        let current_dir = Some(Path::new(".").to_path_buf());
 
-        let paths_to_check = [
-            home_dir_path,
-            Some(current_dir_path)
-        ];
+        // Within each directory, `config.json` takes precedence over `config.toml`.
+        let paths_to_check = [home_dir, current_dir]
+            .into_iter()
+            .flatten()
+            .flat_map(|dir| [dir.join("config.json"), dir.join("config.toml")]);
 
-        for path in paths_to_check.iter().flatten() {
+        for path in paths_to_check {
             if path.exists() {
-                let content = fs::read_to_string(path)?;
-                let config: Config = serde_json::from_str(&content)?;
-                return Ok(config);
+                return Self::load_from_file(&path);
             }
         }
 
         // If no config file is found, return a config with all `None` values.
-        Ok(Config {
-            kubeconfig: None, context: None, namespace: None,
-            ssh_public_key_path: None, ssh_username: None, local_socks_port: None,
-            pod_ttl_seconds: None, pod_image: None, pod_resources: None,
-            pod_labels: None, pod_annotations: None, log_level: None,
-        })
+        Ok(Config::builder().build())
+    }
+
+    fn load_from_file(path: &Path) -> Result<Config, ConfigError> {
+        let content = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Ok(toml::from_str(&content)?),
+            _ => Ok(serde_json::from_str(&content)?),
+        }
     }
 
     fn expand_tilde<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
@@ -49,33 +50,24 @@ impl ConfigService for ConfigServiceImpl {
 
 #[cfg(test)]
 mod tests {
-    use k8socks_traits::config::Config;
+    use super::ConfigServiceImpl;
+    use k8socks_traits::config::{Config, ConfigError, ConfigService, PodResources};
     use merge::Merge;
 
     #[test]
     fn test_config_precedence() {
         // 1. Start with defaults
         let mut final_config = Config::default();
-        assert_eq!(final_config.namespace, Some("default".to_string()));
+        assert_eq!(final_config.namespace, None);
         assert_eq!(final_config.local_socks_port, Some(1080));
         assert_eq!(final_config.context, None);
 
         // 2. Create a "file" config layer
-        let file_config = Config {
-            namespace: Some("from-file".to_string()),
-            local_socks_port: Some(9999),
-            context: Some("file-context".to_string()),
-            // Fill in the rest with default values to satisfy the struct initialization
-            kubeconfig: None,
-            ssh_public_key_path: None,
-            ssh_username: None,
-            pod_ttl_seconds: None,
-            pod_image: None,
-            pod_resources: None,
-            pod_labels: None,
-            pod_annotations: None,
-            log_level: None,
-        };
+        let file_config = Config::builder()
+            .namespace("from-file".to_string())
+            .local_socks_port(9999)
+            .context("file-context".to_string())
+            .build();
 
         // Merge file config over defaults
         final_config.merge(file_config);
@@ -84,20 +76,10 @@ mod tests {
         assert_eq!(final_config.context, Some("file-context".to_string()));
 
         // 3. Create a "CLI" config layer
-        let cli_config = Config {
-            namespace: Some("from-cli".to_string()),
-            local_socks_port: None,
-            context: None,
-            kubeconfig: Some("/path/from/cli".to_string()),
-            ssh_public_key_path: None,
-            ssh_username: None,
-            pod_ttl_seconds: None,
-            pod_image: None,
-            pod_resources: None,
-            pod_labels: None,
-            pod_annotations: None,
-            log_level: None,
-        };
+        let cli_config = Config::builder()
+            .namespace("from-cli".to_string())
+            .kubeconfig("/path/from/cli".to_string())
+            .build();
 
         // Merge CLI config over the existing config
         final_config.merge(cli_config);
@@ -109,4 +91,570 @@ mod tests {
         assert_eq!(final_config.kubeconfig, Some("/path/from/cli".to_string()));
         assert_eq!(final_config.ssh_username, Some("k8socks".to_string()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_max_lifetime_cannot_be_raised_by_later_layers() {
+        let mut config = Config::default();
+        config.merge(Config {
+            pod_max_lifetime_seconds: Some(3600),
+            ..Default::default()
+        });
+        assert_eq!(config.pod_max_lifetime_seconds, Some(3600));
+
+        // A larger value from the CLI does not lift the cap...
+        config.merge(Config {
+            pod_max_lifetime_seconds: Some(7200),
+            ..Default::default()
+        });
+        assert_eq!(config.pod_max_lifetime_seconds, Some(3600));
+
+        // ...but a smaller one tightens it, and an unset one keeps it.
+        config.merge(Config {
+            pod_max_lifetime_seconds: Some(600),
+            ..Default::default()
+        });
+        config.merge(Config::default());
+        assert_eq!(config.pod_max_lifetime_seconds, Some(600));
+    }
+
+    #[test]
+    fn test_pod_resources_merge_per_field() {
+        let resources = |cpu: Option<&str>, memory: Option<&str>| {
+            Some(PodResources {
+                cpu: cpu.map(str::to_string),
+                memory: memory.map(str::to_string),
+            })
+        };
+        let mut config = Config {
+            pod_resources: resources(None, Some("128Mi")),
+            ..Default::default()
+        };
+        config.merge(Config {
+            pod_resources: resources(Some("100m"), None),
+            ..Default::default()
+        });
+        assert_eq!(config.pod_resources, resources(Some("100m"), Some("128Mi")));
+
+        // A layer without pod_resources keeps them as they are.
+        config.merge(Config {
+            pod_resources: None,
+            ..Default::default()
+        });
+        assert_eq!(config.pod_resources, resources(Some("100m"), Some("128Mi")));
+    }
+
+    #[test]
+    fn test_parse_pod_resources() {
+        let resources = |cpu: Option<&str>, memory: Option<&str>| PodResources {
+            cpu: cpu.map(str::to_string),
+            memory: memory.map(str::to_string),
+        };
+        assert_eq!("cpu=250m".parse(), Ok(resources(Some("250m"), None)));
+        assert_eq!("memory=256Mi".parse(), Ok(resources(None, Some("256Mi"))));
+        assert_eq!("cpu=1,memory=1Gi".parse(), Ok(resources(Some("1"), Some("1Gi"))));
+        assert_eq!(" memory = 1Gi , cpu = 500m ".parse(), Ok(resources(Some("500m"), Some("1Gi"))));
+
+        for input in ["", "cpu", "cpu=", "gpu=1", "cpu=1,cpu=2", "cpu=1,,memory=1Gi"] {
+            assert!(input.parse::<PodResources>().is_err(), "'{}' should be rejected", input);
+        }
+    }
+
+    #[test]
+    fn test_resources_flag_merges_over_file() {
+        let file = || Config {
+            pod_resources: Some(PodResources {
+                cpu: Some("100m".to_string()),
+                memory: Some("128Mi".to_string()),
+            }),
+            ..Default::default()
+        };
+        for (flag, cpu, memory) in [
+            ("cpu=250m", "250m", "128Mi"),
+            ("memory=1Gi", "100m", "1Gi"),
+            ("cpu=2,memory=4Gi", "2", "4Gi"),
+        ] {
+            let mut config = file();
+            config.merge(Config::builder().pod_resources(flag.parse::<PodResources>().unwrap()).build());
+            let resources = config.pod_resources.unwrap();
+            assert_eq!(resources.cpu.as_deref(), Some(cpu), "{}", flag);
+            assert_eq!(resources.memory.as_deref(), Some(memory), "{}", flag);
+        }
+
+        // Without the flag, the file's resources stay.
+        let mut config = file();
+        config.merge(Config::builder().pod_resources(None).build());
+        assert_eq!(config.pod_resources, file().pod_resources);
+    }
+
+    #[test]
+    fn test_validate_ssh_jump_host() {
+        for jump in ["bastion", "me@bastion.example.com", "bastion:2222", "a@hop1,hop2:22"] {
+            let config = Config {
+                ssh_jump_host: Some(jump.to_string()),
+                ..Default::default()
+            };
+            assert!(config.validate().is_ok(), "{} should be valid", jump);
+        }
+        for jump in ["", "@bastion", "bastion:ssh", "-oProxyCommand=x", "hop1,,hop2", "two words"] {
+            let config = Config {
+                ssh_jump_host: Some(jump.to_string()),
+                ..Default::default()
+            };
+            assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))), "{} should be invalid", jump);
+        }
+    }
+
+    #[test]
+    fn test_validate_placeholders() {
+        let labels = |pairs: &[(&str, &str)]| {
+            Some(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+        };
+        let config = Config {
+            pod_labels: labels(&[("app", "k8socks"), ("owner", "{user}"), ("on", "{date}")]),
+            pod_annotations: labels(&[("by", "{user}@{hostname}"), ("json", r#"{"a": 1}"#)]),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        for config in [
+            Config {
+                pod_labels: labels(&[("app", "k8socks"), ("owner", "{usr}")]),
+                ..Default::default()
+            },
+            Config {
+                pod_annotations: labels(&[("by", "{user} {nope}")]),
+                ..Default::default()
+            },
+            // Nothing left to select the pods by.
+            Config {
+                pod_labels: labels(&[("owner", "{user}")]),
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))), "{:?}", config.pod_labels);
+        }
+
+        let config = Config {
+            pod_labels: labels(&[("owner", "{user}")]),
+            pod_selector_label: Some("owner".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let file: Config = toml::from_str(
+            r#"
+namespace = "proxy"
+local_socks_port = 1081
+
+[profiles.prod]
+context = "prod-eu"
+local_socks_port = 1090
+"#,
+        )
+        .unwrap();
+        assert!(file.validate().is_ok());
+
+        let mut config = Config::default();
+        config.merge(file);
+        let mut prod = config.clone();
+        prod.apply_profile("prod").unwrap();
+        assert_eq!(prod.context.as_deref(), Some("prod-eu"));
+        assert_eq!(prod.local_socks_port, Some(1090));
+        // What the profile leaves alone comes from the rest of the file.
+        assert_eq!(prod.namespace.as_deref(), Some("proxy"));
+
+        let err = config.apply_profile("staging").unwrap_err().to_string();
+        assert!(err.contains("'staging' not found") && err.contains("prod"), "{}", err);
+        let err = Config::default().apply_profile("prod").unwrap_err().to_string();
+        assert!(err.contains("defines no profiles"), "{}", err);
+
+        let inner = Config {
+            profiles: Some(Default::default()),
+            ..Config::builder().build()
+        };
+        let nested = Config {
+            profiles: Some([("a".to_string(), inner)].into()),
+            ..Default::default()
+        };
+        assert!(matches!(nested.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_validate_ssh_algorithms() {
+        for algorithms in ["aes256-ctr", "aes256-gcm@openssh.com,chacha20-poly1305@openssh.com", "+aes128-cbc"] {
+            let config = Config {
+                ssh_ciphers: Some(algorithms.to_string()),
+                ssh_kex_algorithms: Some(algorithms.to_string()),
+                ..Default::default()
+            };
+            assert!(config.validate().is_ok(), "{} should be valid", algorithms);
+        }
+        for algorithms in ["", "aes256-ctr,", "aes256-ctr aes128-ctr", "x\nProxyCommand=y"] {
+            for config in [
+                Config {
+                    ssh_ciphers: Some(algorithms.to_string()),
+                    ..Default::default()
+                },
+                Config {
+                    ssh_kex_algorithms: Some(algorithms.to_string()),
+                    ..Default::default()
+                },
+            ] {
+                assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))), "{} should be invalid", algorithms);
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate_sshd_config() {
+        let sshd = |lines: &str| Config {
+            pod_image_kind: Some("sshd".to_string()),
+            sshd_config: Some(lines.to_string()),
+            ..Default::default()
+        };
+        for lines in ["MaxSessions 4", "# comment\n\nMaxSessions 4\nAllowUsers root", "ClientAliveInterval=30"] {
+            assert!(sshd(lines).validate().is_ok(), "{:?} should be valid", lines);
+        }
+        for lines in ["MaxSessions", "MaxSessions ", "Max-Sessions 4", "Match User root", "Banner a\tb", "'x y"] {
+            assert!(matches!(sshd(lines).validate(), Err(ConfigError::Invalid(_))), "{:?} should be invalid", lines);
+        }
+
+        let linuxserver = Config {
+            pod_image_kind: None,
+            ..sshd("MaxSessions 4")
+        };
+        assert!(matches!(linuxserver.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_validate_max_connections() {
+        let config = |max_connections| Config {
+            max_connections: Some(max_connections),
+            ..Default::default()
+        };
+        assert!(config(1).validate().is_ok());
+        assert!(matches!(config(0).validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_validate_rate_limit() {
+        let config = |rate_limit| Config {
+            rate_limit: Some(rate_limit),
+            ..Default::default()
+        };
+        assert!(config(1).validate().is_ok());
+        assert!(matches!(config(0).validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_validate_production_context_pattern() {
+        let config = |pattern: &str| Config {
+            production_context_pattern: Some(pattern.to_string()),
+            ..Default::default()
+        };
+        assert!(config("prod|live").validate().is_ok());
+        assert!(matches!(config("prod(").validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_validate_ssh_target_host() {
+        for host in ["127.0.0.1", "::1", "tunnel.internal"] {
+            let config = Config {
+                ssh_target_host: Some(host.to_string()),
+                ..Default::default()
+            };
+            assert!(config.validate().is_ok(), "{} should be valid", host);
+        }
+        for host in ["", "me@tunnel", "two words"] {
+            let config = Config {
+                ssh_target_host: Some(host.to_string()),
+                ..Default::default()
+            };
+            assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))), "{} should be invalid", host);
+        }
+    }
+
+    #[test]
+    fn test_validate_restart_policy() {
+        assert!(Config::default().validate().is_ok());
+
+        let config = Config {
+            pod_restart_policy: Some("OnFailure".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let config = Config {
+            pod_restart_policy: Some("Sometimes".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_validate_host_keys_init() {
+        let config = Config {
+            pod_host_keys_init: Some(true),
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+
+        let config = Config {
+            pod_image_kind: Some("sshd".to_string()),
+            ..config
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_config_builder() {
+        let config = Config::builder()
+            .namespace("proxy".to_string())
+            .local_socks_port(1081)
+            .pod_ttl_seconds(None)
+            .ssh_public_key_paths(vec!["~/.ssh/a.pub".to_string()])
+            .build();
+        assert_eq!(config.namespace.as_deref(), Some("proxy"));
+        assert_eq!(config.local_socks_port, Some(1081));
+        assert_eq!(config.pod_ttl_seconds, None);
+        assert_eq!(config.ssh_public_key_paths, Some(vec!["~/.ssh/a.pub".to_string()]));
+        // Unset fields carry no defaults, so the builder is safe to merge as a layer.
+        assert_eq!(config.pod_image, None);
+        assert_eq!(Config::builder().build(), serde_json::from_str::<Config>("{}").unwrap());
+
+        let mut merged = Config::default();
+        merged.merge(config);
+        assert_eq!(merged.local_socks_port, Some(1081));
+        assert_eq!(merged.pod_ttl_seconds, Config::default().pod_ttl_seconds);
+    }
+
+    #[test]
+    fn test_per_context() {
+        let config = Config::default();
+        assert_eq!(config.per_context(), std::slice::from_ref(&config));
+
+        let config = Config {
+            contexts: Some(vec!["prod".to_string(), "staging".to_string()]),
+            forwarded_local_port: Some(20000),
+            health_port: Some(8080),
+            ..Default::default()
+        };
+        let sessions = config.per_context();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].context.as_deref(), Some("prod"));
+        assert_eq!(sessions[1].context.as_deref(), Some("staging"));
+        assert_eq!(
+            sessions.iter().map(|s| s.local_socks_port).collect::<Vec<_>>(),
+            [Some(1080), Some(1081)]
+        );
+        assert_eq!(sessions[1].forwarded_local_port, Some(20001));
+        assert_eq!(sessions[1].health_port, Some(8081));
+        assert!(sessions.iter().all(|s| s.contexts.is_none()));
+
+        // Free ports stay free.
+        let config = Config {
+            local_socks_port: Some(0),
+            ..config
+        };
+        assert!(config.per_context().iter().all(|s| s.local_socks_port == Some(0)));
+    }
+
+    #[test]
+    fn test_validate_contexts() {
+        let config = Config {
+            contexts: Some(vec!["a".to_string(), "b".to_string()]),
+            local_socks_port: Some(65535),
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+
+        let config = Config {
+            local_socks_port: Some(0),
+            ..config
+        };
+        assert!(config.validate().is_ok());
+
+        let config = Config {
+            contexts: Some(vec!["a".to_string(), " ".to_string()]),
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_validate_port_conflicts() {
+        let invalid = |config: Config| match config.validate() {
+            Err(ConfigError::Invalid(message)) => message,
+            other => panic!("expected an invalid config, got {:?}", other),
+        };
+
+        let config = Config {
+            forwarded_local_port: Some(20000),
+            health_port: Some(8080),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        // The default SOCKS port counts as well.
+        assert_eq!(
+            invalid(Config {
+                health_port: Some(1080),
+                ..Default::default()
+            }),
+            "local_socks_port 1080 and health_port 1080 overlap; each needs ports of its own"
+        );
+        assert_eq!(
+            invalid(Config {
+                forwarded_local_port: Some(9000),
+                health_port: Some(9000),
+                ..Default::default()
+            }),
+            "forwarded_local_port 9000 and health_port 9000 overlap; each needs ports of its own"
+        );
+
+        // Free ports never conflict.
+        let config = Config {
+            local_socks_port: Some(0),
+            forwarded_local_port: Some(0),
+            health_port: Some(0),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        // Each context gets the next port, so adjacent ports collide.
+        let contexts = Config {
+            contexts: Some(vec!["a".to_string(), "b".to_string(), "c".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid(Config {
+                health_port: Some(1082),
+                ..contexts.clone()
+            }),
+            "local_socks_port 1080-1082 and health_port 1082-1084 overlap; each needs ports of its own"
+        );
+        assert!(Config {
+            health_port: Some(1083),
+            ..contexts
+        }
+        .validate()
+        .is_ok());
+    }
+
+    #[test]
+    fn test_validate_k8s_names() {
+        let invalid = |config: Config| match config.validate() {
+            Err(ConfigError::Invalid(message)) => message,
+            other => panic!("expected an invalid config, got {:?}", other),
+        };
+        let labels = |pairs: &[(&str, &str)]| {
+            Some(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+        };
+
+        let config = Config {
+            namespace: Some("team-a".to_string()),
+            pod_labels: labels(&[("app", "k8socks"), ("example.com/owner", "alice_b.2"), ("empty", "")]),
+            pod_annotations: labels(&[("k8socks.io/created-by", "CORP\\alice smith")]),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let long_namespace = "a".repeat(64);
+        for namespace in ["Team-A", "team_a", "-team", "", long_namespace.as_str()] {
+            let message = invalid(Config {
+                namespace: Some(namespace.to_string()),
+                ..Default::default()
+            });
+            assert!(message.starts_with(&format!("namespace '{}' ", namespace)), "{}", message);
+        }
+        let message = invalid(Config {
+            pod_hostname: Some("proxy.local".to_string()),
+            ..Default::default()
+        });
+        assert!(message.starts_with("pod_hostname 'proxy.local' "), "{}", message);
+        let message = invalid(Config {
+            pod_subdomain: Some("K8socks".to_string()),
+            ..Default::default()
+        });
+        assert!(message.starts_with("pod_subdomain 'K8socks' "), "{}", message);
+        assert_eq!(
+            invalid(Config {
+                namespace: Some("Team_A".to_string()),
+                ..Default::default()
+            }),
+            "namespace 'Team_A' may only contain lowercase letters, digits and '-'"
+        );
+
+        assert_eq!(
+            invalid(Config {
+                pod_labels: labels(&[("app", "k8socks proxy")]),
+                ..Default::default()
+            }),
+            "pod_labels value 'k8socks proxy' of 'app' may only contain letters, digits, '-', '_' and '.'"
+        );
+        let too_long = "v".repeat(64);
+        assert!(invalid(Config {
+            pod_labels: labels(&[("app", too_long.as_str())]),
+            ..Default::default()
+        })
+        .contains("must be at most 63 characters long"));
+        for key in ["Example.com/app", "/app", "example.com/", "_app", "a/b/c"] {
+            let message = invalid(Config {
+                pod_labels: labels(&[(key, "x")]),
+                ..Default::default()
+            });
+            assert!(message.starts_with(&format!("pod_labels key '{}' ", key)), "{}", message);
+        }
+        assert!(invalid(Config {
+            pod_annotations: labels(&[("bad key", "anything goes here")]),
+            ..Default::default()
+        })
+        .starts_with("pod_annotations key 'bad key' "));
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let config = Config {
+            pod_labels: Some([("team".to_string(), "platform".to_string())].into()),
+            ..Default::default()
+        };
+
+        let serialized = toml::to_string(&config).unwrap();
+        let parsed: Config = toml::from_str(&serialized).unwrap();
+        assert_eq!(parsed, config);
+    }
+
+    #[test]
+    fn test_toml_rejects_unknown_fields() {
+        let result: Result<Config, _> = toml::from_str("namespce = \"typo\"\n");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_load_from_file_detects_toml() {
+        let dir = std::env::temp_dir().join(format!("k8socks-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let toml_path = dir.join("config.toml");
+        std::fs::write(&toml_path, "namespace = \"from-toml\"\nlocal_socks_port = 1081\n").unwrap();
+        let config = ConfigServiceImpl::load_from_file(&toml_path).unwrap();
+        assert_eq!(config.namespace, Some("from-toml".to_string()));
+        assert_eq!(config.local_socks_port, Some(1081));
+
+        std::fs::write(&toml_path, "namespce = \"typo\"\n").unwrap();
+        assert!(matches!(
+            ConfigServiceImpl::load_from_file(&toml_path),
+            Err(ConfigError::ParseToml(_))
+        ));
+
+        let json_path = dir.join("config.json");
+        std::fs::write(&json_path, r#"{"namespace": "from-json"}"#).unwrap();
+        let config = ConfigServiceImpl::load_from_file(&json_path).unwrap();
+        assert_eq!(config.namespace, Some("from-json".to_string()));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,165 @@
+//! Named sessions: `deploy --session-name <name>` records its pod in
+//! `~/.k8socks/sessions/<name>.json`, so that the next `deploy` with the same
+//! name can reuse the pod instead of deploying a new one.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use directories::BaseDirs;
+use serde::{Deserialize, Serialize};
+use k8socks_traits::k8s::PodRef;
+
+/// The pod a named session runs on.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionRecord {
+    pub name: String,
+    pub pod: String,
+    pub namespace: String,
+    #[serde(default)]
+    pub context: Option<String>,
+}
+
+impl SessionRecord {
+    pub fn pod_ref(&self) -> PodRef {
+        PodRef::new(&self.pod, &self.namespace)
+    }
+}
+
+/// Session names end up in file names, so they are kept to letters, digits,
+/// `-`, `_` and `.`, and may not start with a `.`.
+pub fn validate_session_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > 64 {
+        return Err(format!("session name '{}' must be 1 to 64 characters long", name));
+    }
+    if name.starts_with('.') || !name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) {
+        return Err(format!(
+            "session name '{}' may only contain letters, digits, '-', '_' and '.', and may not start with '.'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Whether a recorded pod can no longer be reused: it is gone, or it is not
+/// running (e.g. it failed once its TTL had passed).
+pub fn is_stale(pod: Option<&PodRef>) -> bool {
+    pod.and_then(|p| p.phase.as_deref()) != Some("Running")
+}
+
+/// The session records in one directory.
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The store in `~/.k8socks/sessions`, if the home directory is known.
+    pub fn in_home_dir() -> Option<Self> {
+        BaseDirs::new().map(|dirs| Self::new(dirs.home_dir().join(".k8socks").join("sessions")))
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+
+    /// Reads the record of session `name`. A missing or unreadable record is
+    /// `None`; the caller deploys a new pod and overwrites it.
+    pub fn load(&self, name: &str) -> Option<SessionRecord> {
+        let content = fs::read_to_string(self.path(name)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self, record: &SessionRecord) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let content = serde_json::to_string_pretty(record).map_err(io::Error::other)?;
+        fs::write(self.path(&record.name), content)
+    }
+
+    /// Removes the record of session `name`, if there is one.
+    pub fn remove(&self, name: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(name)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// All readable records, in no particular order.
+    pub fn list(&self) -> Vec<SessionRecord> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_str()?.strip_suffix(".json")?.to_string();
+                self.load(&name)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, pod: &str) -> SessionRecord {
+        SessionRecord {
+            name: name.to_string(),
+            pod: pod.to_string(),
+            namespace: "team-a".to_string(),
+            context: None,
+        }
+    }
+
+    #[test]
+    fn test_session_store_round_trip() {
+        let dir = std::env::temp_dir().join(format!("k8socks-sessions-{}", std::process::id()));
+        let store = SessionStore::new(&dir);
+        assert_eq!(store.load("dev"), None);
+        assert!(store.list().is_empty());
+
+        store.save(&record("dev", "k8socks-abc123")).unwrap();
+        store.save(&record("ci", "k8socks-def456")).unwrap();
+        assert_eq!(store.load("dev"), Some(record("dev", "k8socks-abc123")));
+        assert_eq!(store.list().len(), 2);
+
+        // A newer pod replaces the old one.
+        store.save(&record("dev", "k8socks-fff000")).unwrap();
+        assert_eq!(store.load("dev").unwrap().pod, "k8socks-fff000");
+
+        store.remove("dev").unwrap();
+        store.remove("dev").unwrap();
+        assert_eq!(store.load("dev"), None);
+
+        // A damaged record counts as missing.
+        fs::write(dir.join("broken.json"), "{").unwrap();
+        assert_eq!(store.load("broken"), None);
+        assert_eq!(store.list(), [record("ci", "k8socks-def456")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_stale() {
+        let mut pod = PodRef::new("k8socks-abc123", "team-a");
+        assert!(is_stale(None));
+        assert!(is_stale(Some(&pod)));
+        for (phase, stale) in [("Pending", true), ("Failed", true), ("Succeeded", true), ("Running", false)] {
+            pod.phase = Some(phase.to_string());
+            assert_eq!(is_stale(Some(&pod)), stale, "{}", phase);
+        }
+    }
+
+    #[test]
+    fn test_validate_session_name() {
+        for name in ["dev", "team-a_1", "v1.2"] {
+            assert!(validate_session_name(name).is_ok(), "{}", name);
+        }
+        for name in ["", ".hidden", "../etc/passwd", "a/b", "two words"] {
+            assert!(validate_session_name(name).is_err(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! A minimal ssh-agent client: just enough of the agent protocol to list the
+//! public keys the agent holds, so that users who keep their keys only in the
+//! agent need no `.pub` file.
+
+use std::io::{self, Read, Write};
+
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+/// Agents cap their messages at 256 KiB as well.
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+/// A public key held by the agent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgentKey {
+    pub key_type: String,
+    pub blob: Vec<u8>,
+    pub comment: String,
+}
+
+impl AgentKey {
+    /// The key as an authorized_keys line, e.g. `ssh-ed25519 AAAA... me@laptop`.
+    pub fn to_authorized_key(&self) -> String {
+        let line = format!("{} {} {}", self.key_type, base64_encode(&self.blob), self.comment);
+        line.trim_end().to_string()
+    }
+}
+
+/// Lists the keys of the agent listening on `$SSH_AUTH_SOCK`.
+#[cfg(unix)]
+pub fn list_keys() -> io::Result<Vec<AgentKey>> {
+    let socket = std::env::var_os("SSH_AUTH_SOCK")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "SSH_AUTH_SOCK is not set; is ssh-agent running?"))?;
+    let mut stream = std::os::unix::net::UnixStream::connect(socket)?;
+    request_identities(&mut stream)
+}
+
+#[cfg(not(unix))]
+pub fn list_keys() -> io::Result<Vec<AgentKey>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "reading keys from ssh-agent is only supported on Unix"))
+}
+
+/// Picks the key to use: the first one without a `selector`, otherwise the one
+/// at that (0-based) index or the first whose comment contains it.
+pub fn select_key<'a>(keys: &'a [AgentKey], selector: Option<&str>) -> Result<&'a AgentKey, String> {
+    if keys.is_empty() {
+        return Err("ssh-agent holds no keys; add one with ssh-add".to_string());
+    }
+    let Some(selector) = selector else {
+        return Ok(&keys[0]);
+    };
+    let key = match selector.parse::<usize>() {
+        Ok(index) => keys.get(index),
+        Err(_) => keys.iter().find(|key| key.comment.contains(selector)),
+    };
+    key.ok_or_else(|| {
+        format!(
+            "no key in ssh-agent matches '{}'; `ssh-add -l` lists its {} key(s)",
+            selector,
+            keys.len()
+        )
+    })
+}
+
+fn request_identities<S: Read + Write>(stream: &mut S) -> io::Result<Vec<AgentKey>> {
+    stream.write_all(&[0, 0, 0, 1, SSH_AGENTC_REQUEST_IDENTITIES])?;
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 || len > MAX_MESSAGE_LEN {
+        return Err(invalid("bad message length"));
+    }
+    let mut message = vec![0u8; len];
+    stream.read_exact(&mut message)?;
+    parse_identities_answer(&message)
+}
+
+fn parse_identities_answer(message: &[u8]) -> io::Result<Vec<AgentKey>> {
+    let (&kind, mut rest) = message.split_first().ok_or_else(|| invalid("empty message"))?;
+    if kind != SSH_AGENT_IDENTITIES_ANSWER {
+        return Err(invalid("the agent refused to list its keys"));
+    }
+    let count = read_u32(&mut rest)?;
+    let mut keys = Vec::new();
+    for _ in 0..count {
+        let blob = read_string(&mut rest)?.to_vec();
+        let comment = String::from_utf8_lossy(read_string(&mut rest)?).into_owned();
+        // The blob starts with the key type, e.g. `ssh-ed25519`.
+        let mut blob_rest = blob.as_slice();
+        let key_type = String::from_utf8_lossy(read_string(&mut blob_rest)?).into_owned();
+        keys.push(AgentKey { key_type, blob, comment });
+    }
+    Ok(keys)
+}
+
+fn read_u32(buf: &mut &[u8]) -> io::Result<u32> {
+    let (bytes, rest) = buf.split_first_chunk::<4>().ok_or_else(|| invalid("truncated message"))?;
+    *buf = rest;
+    Ok(u32::from_be_bytes(*bytes))
+}
+
+fn read_string<'a>(buf: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let len = read_u32(buf)? as usize;
+    if buf.len() < len {
+        return Err(invalid("truncated message"));
+    }
+    let (string, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(string)
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("unexpected answer from ssh-agent: {}", reason))
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (u32::from(chunk[0]) << 16)
+            | (u32::from(*chunk.get(1).unwrap_or(&0)) << 8)
+            | u32::from(*chunk.get(2).unwrap_or(&0));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(bytes: &[u8]) -> Vec<u8> {
+        let mut out = (bytes.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(bytes);
+        out
+    }
+
+    fn key(key_type: &str, comment: &str) -> AgentKey {
+        let mut blob = string(key_type.as_bytes());
+        blob.extend(string(&[1, 2, 3]));
+        AgentKey {
+            key_type: key_type.to_string(),
+            blob,
+            comment: comment.to_string(),
+        }
+    }
+
+    fn answer(keys: &[AgentKey]) -> Vec<u8> {
+        let mut message = vec![SSH_AGENT_IDENTITIES_ANSWER];
+        message.extend((keys.len() as u32).to_be_bytes());
+        for key in keys {
+            message.extend(string(&key.blob));
+            message.extend(string(key.comment.as_bytes()));
+        }
+        message
+    }
+
+    #[test]
+    fn test_base64_encode() {
+        for (input, expected) in [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foobar", "Zm9vYmFy")] {
+            assert_eq!(base64_encode(input.as_bytes()), expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_identities_answer() {
+        let keys = [key("ssh-ed25519", "me@laptop"), key("ssh-rsa", "")];
+        assert_eq!(parse_identities_answer(&answer(&keys)).unwrap(), keys);
+        assert_eq!(keys[1].to_authorized_key(), format!("ssh-rsa {}", base64_encode(&keys[1].blob)));
+
+        assert!(parse_identities_answer(&[]).is_err());
+        // SSH_AGENT_FAILURE
+        assert!(parse_identities_answer(&[5]).is_err());
+        let truncated = answer(&keys);
+        assert!(parse_identities_answer(&truncated[..truncated.len() - 3]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_request_identities() {
+        let (mut client, mut agent) = std::os::unix::net::UnixStream::pair().unwrap();
+        let keys = vec![key("ssh-ed25519", "me@laptop")];
+        let message = answer(&keys);
+        let server = std::thread::spawn(move || {
+            let mut request = [0u8; 5];
+            agent.read_exact(&mut request).unwrap();
+            assert_eq!(request, [0, 0, 0, 1, SSH_AGENTC_REQUEST_IDENTITIES]);
+            agent.write_all(&(message.len() as u32).to_be_bytes()).unwrap();
+            agent.write_all(&message).unwrap();
+        });
+        assert_eq!(request_identities(&mut client).unwrap(), keys);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_select_key() {
+        let keys = [key("ssh-ed25519", "me@laptop"), key("ssh-rsa", "work key")];
+        assert_eq!(select_key(&keys, None).unwrap(), &keys[0]);
+        assert_eq!(select_key(&keys, Some("1")).unwrap(), &keys[1]);
+        assert_eq!(select_key(&keys, Some("work")).unwrap(), &keys[1]);
+        assert!(select_key(&keys, Some("2")).is_err());
+        assert!(select_key(&keys, Some("home")).unwrap_err().contains("'home'"));
+        assert!(select_key(&[], None).unwrap_err().contains("no keys"));
+    }
+}
@@ -0,0 +1,339 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::{ConfigMap, Event, Namespace, Pod, ResourceQuota};
+use kube::api::{
+    Api, AttachParams, AttachedProcess, DeleteParams, ListParams, LogParams, ObjectList, Portforwarder, PostParams,
+};
+use kube::{Client, Error as KubeError, ResourceExt};
+use tokio::io::{self, AsyncRead, AsyncWrite};
+use tracing::error;
+use k8socks_traits::k8s::K8sError;
+use k8socks_util::rate_limit::RateLimiter;
+
+/// A byte stream to a port of a pod.
+pub trait PortStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> PortStream for T {}
+
+/// The Kubernetes API calls `K8sServiceImpl` makes, so that tests can swap the
+/// cluster for a fake. Besides pods this covers the pod events, namespaces,
+/// resource quotas and owner ConfigMaps the service needs.
+#[async_trait]
+pub trait PodApi: Send + Sync {
+    async fn create(&self, namespace: &str, pod: &Pod) -> Result<Pod, KubeError>;
+    async fn get(&self, namespace: &str, name: &str) -> Result<Pod, KubeError>;
+    async fn delete(&self, namespace: &str, name: &str, params: &DeleteParams) -> Result<(), KubeError>;
+    /// Lists pods in `namespace`, or across all namespaces when it is `None`.
+    async fn list(&self, namespace: Option<&str>, params: &ListParams) -> Result<ObjectList<Pod>, KubeError>;
+    async fn list_namespaces(&self) -> Result<Vec<String>, KubeError>;
+    async fn namespace_exists(&self, name: &str) -> Result<bool, KubeError>;
+    async fn list_events(&self, namespace: &str, params: &ListParams) -> Result<Vec<Event>, KubeError>;
+    async fn list_resource_quotas(&self, namespace: &str) -> Result<Vec<ResourceQuota>, KubeError>;
+    async fn create_config_map(&self, namespace: &str, config_map: &ConfigMap) -> Result<ConfigMap, KubeError>;
+    async fn delete_config_map(&self, namespace: &str, name: &str, params: &DeleteParams) -> Result<(), KubeError>;
+    async fn logs(&self, namespace: &str, name: &str, params: &LogParams) -> Result<String, KubeError>;
+    /// Opens a stream to `port` of the pod. Fails with `PortForwardFailed`
+    /// when the API server did not set up the stream, e.g. because nothing
+    /// listens on the port yet.
+    async fn portforward(&self, namespace: &str, name: &str, port: u16) -> Result<Box<dyn PortStream>, K8sError>;
+    async fn exec(
+        &self,
+        namespace: &str,
+        name: &str,
+        command: Vec<String>,
+        params: &AttachParams,
+    ) -> Result<AttachedProcess, KubeError>;
+}
+
+/// Something that hands out the stream of a forwarded port, which it can do
+/// only once per port: kube's `Portforwarder`, or a fake in tests.
+pub trait TakeStream {
+    fn take_stream(&mut self, port: u16) -> Option<Box<dyn PortStream>>;
+}
+
+impl TakeStream for Portforwarder {
+    fn take_stream(&mut self, port: u16) -> Option<Box<dyn PortStream>> {
+        Portforwarder::take_stream(self, port).map(|stream| Box::new(stream) as Box<dyn PortStream>)
+    }
+}
+
+/// One port-forward connection to a single port of a pod. Its stream can be
+/// taken once; a second `take` is an error rather than a silently missing
+/// stream, and reconnecting means opening a new connection.
+pub struct ForwardConnection<F> {
+    forwarder: F,
+    pod: String,
+    port: u16,
+    taken: bool,
+}
+
+impl<F: TakeStream> ForwardConnection<F> {
+    pub fn new(forwarder: F, pod: &str, port: u16) -> Self {
+        Self {
+            forwarder,
+            pod: pod.to_string(),
+            port,
+            taken: false,
+        }
+    }
+
+    /// Takes the stream. Fails with `PortForwardFailed` when it was taken
+    /// before or the API server did not forward the port.
+    pub fn take(&mut self) -> Result<Box<dyn PortStream>, K8sError> {
+        if self.taken {
+            return Err(K8sError::PortForwardFailed(io::Error::other(format!(
+                "the stream to port {} of pod '{}' was already taken; open a new port-forward to reconnect",
+                self.port, self.pod
+            ))));
+        }
+        self.taken = true;
+        self.forwarder.take_stream(self.port).ok_or_else(|| {
+            K8sError::PortForwardFailed(io::Error::other(format!(
+                "port {} of pod '{}' was not forwarded",
+                self.port, self.pod
+            )))
+        })
+    }
+
+    /// The forwarder, e.g. to wait for it to finish.
+    pub fn into_inner(self) -> F {
+        self.forwarder
+    }
+}
+
+/// `PodApi` backed by a real cluster.
+pub struct KubePodApi {
+    client: Client,
+}
+
+impl KubePodApi {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    fn pods(&self, namespace: &str) -> Api<Pod> {
+        Api::namespaced(self.client.clone(), namespace)
+    }
+}
+
+#[async_trait]
+impl PodApi for KubePodApi {
+    async fn create(&self, namespace: &str, pod: &Pod) -> Result<Pod, KubeError> {
+        self.pods(namespace).create(&PostParams::default(), pod).await
+    }
+
+    async fn get(&self, namespace: &str, name: &str) -> Result<Pod, KubeError> {
+        self.pods(namespace).get(name).await
+    }
+
+    async fn delete(&self, namespace: &str, name: &str, params: &DeleteParams) -> Result<(), KubeError> {
+        self.pods(namespace).delete(name, params).await?;
+        Ok(())
+    }
+
+    async fn list(&self, namespace: Option<&str>, params: &ListParams) -> Result<ObjectList<Pod>, KubeError> {
+        match namespace {
+            Some(namespace) => self.pods(namespace).list(params).await,
+            None => Api::<Pod>::all(self.client.clone()).list(params).await,
+        }
+    }
+
+    async fn list_namespaces(&self) -> Result<Vec<String>, KubeError> {
+        let namespaces: Api<Namespace> = Api::all(self.client.clone());
+        let list = namespaces.list(&ListParams::default()).await?;
+        Ok(list.items.iter().map(|ns| ns.name_any()).collect())
+    }
+
+    async fn namespace_exists(&self, name: &str) -> Result<bool, KubeError> {
+        let namespaces: Api<Namespace> = Api::all(self.client.clone());
+        Ok(namespaces.get_opt(name).await?.is_some())
+    }
+
+    async fn list_events(&self, namespace: &str, params: &ListParams) -> Result<Vec<Event>, KubeError> {
+        let events: Api<Event> = Api::namespaced(self.client.clone(), namespace);
+        Ok(events.list(params).await?.items)
+    }
+
+    async fn list_resource_quotas(&self, namespace: &str) -> Result<Vec<ResourceQuota>, KubeError> {
+        let quotas: Api<ResourceQuota> = Api::namespaced(self.client.clone(), namespace);
+        Ok(quotas.list(&ListParams::default()).await?.items)
+    }
+
+    async fn create_config_map(&self, namespace: &str, config_map: &ConfigMap) -> Result<ConfigMap, KubeError> {
+        let config_maps: Api<ConfigMap> = Api::namespaced(self.client.clone(), namespace);
+        config_maps.create(&PostParams::default(), config_map).await
+    }
+
+    async fn delete_config_map(&self, namespace: &str, name: &str, params: &DeleteParams) -> Result<(), KubeError> {
+        let config_maps: Api<ConfigMap> = Api::namespaced(self.client.clone(), namespace);
+        config_maps.delete(name, params).await?;
+        Ok(())
+    }
+
+    async fn logs(&self, namespace: &str, name: &str, params: &LogParams) -> Result<String, KubeError> {
+        self.pods(namespace).logs(name, params).await
+    }
+
+    async fn portforward(&self, namespace: &str, name: &str, port: u16) -> Result<Box<dyn PortStream>, K8sError> {
+        // A fresh forwarder per call: reconnects must never reuse one whose
+        // stream has been taken.
+        let forwarder = self.pods(namespace).portforward(name, &[port]).await?;
+        let mut connection = ForwardConnection::new(forwarder, name, port);
+        let stream = connection.take()?;
+        // The forwarder finishes once the stream is closed.
+        let name = name.to_string();
+        let forwarder = connection.into_inner();
+        tokio::spawn(async move {
+            if let Err(e) = forwarder.join().await {
+                error!("Port-forward to pod '{}' failed: {}", name, e);
+            }
+        });
+        Ok(stream)
+    }
+
+    async fn exec(
+        &self,
+        namespace: &str,
+        name: &str,
+        command: Vec<String>,
+        params: &AttachParams,
+    ) -> Result<AttachedProcess, KubeError> {
+        self.pods(namespace).exec(name, command, params).await
+    }
+}
+
+/// Wraps a `PodApi` so that its calls pass a shared rate limiter first, to
+/// stay clear of the API server's priority-and-fairness throttling. Opening a
+/// port-forward or exec counts as one call, however long it stays open.
+pub struct RateLimitedPodApi {
+    inner: Arc<dyn PodApi>,
+    limiter: RateLimiter,
+}
+
+impl RateLimitedPodApi {
+    pub fn new(inner: Arc<dyn PodApi>, limiter: RateLimiter) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+#[async_trait]
+impl PodApi for RateLimitedPodApi {
+    async fn create(&self, namespace: &str, pod: &Pod) -> Result<Pod, KubeError> {
+        self.limiter.acquire().await;
+        self.inner.create(namespace, pod).await
+    }
+
+    async fn get(&self, namespace: &str, name: &str) -> Result<Pod, KubeError> {
+        self.limiter.acquire().await;
+        self.inner.get(namespace, name).await
+    }
+
+    async fn delete(&self, namespace: &str, name: &str, params: &DeleteParams) -> Result<(), KubeError> {
+        self.limiter.acquire().await;
+        self.inner.delete(namespace, name, params).await
+    }
+
+    async fn list(&self, namespace: Option<&str>, params: &ListParams) -> Result<ObjectList<Pod>, KubeError> {
+        self.limiter.acquire().await;
+        self.inner.list(namespace, params).await
+    }
+
+    async fn list_namespaces(&self) -> Result<Vec<String>, KubeError> {
+        self.limiter.acquire().await;
+        self.inner.list_namespaces().await
+    }
+
+    async fn namespace_exists(&self, name: &str) -> Result<bool, KubeError> {
+        self.limiter.acquire().await;
+        self.inner.namespace_exists(name).await
+    }
+
+    async fn list_events(&self, namespace: &str, params: &ListParams) -> Result<Vec<Event>, KubeError> {
+        self.limiter.acquire().await;
+        self.inner.list_events(namespace, params).await
+    }
+
+    async fn list_resource_quotas(&self, namespace: &str) -> Result<Vec<ResourceQuota>, KubeError> {
+        self.limiter.acquire().await;
+        self.inner.list_resource_quotas(namespace).await
+    }
+
+    async fn create_config_map(&self, namespace: &str, config_map: &ConfigMap) -> Result<ConfigMap, KubeError> {
+        self.limiter.acquire().await;
+        self.inner.create_config_map(namespace, config_map).await
+    }
+
+    async fn delete_config_map(&self, namespace: &str, name: &str, params: &DeleteParams) -> Result<(), KubeError> {
+        self.limiter.acquire().await;
+        self.inner.delete_config_map(namespace, name, params).await
+    }
+
+    async fn logs(&self, namespace: &str, name: &str, params: &LogParams) -> Result<String, KubeError> {
+        self.limiter.acquire().await;
+        self.inner.logs(namespace, name, params).await
+    }
+
+    async fn portforward(&self, namespace: &str, name: &str, port: u16) -> Result<Box<dyn PortStream>, K8sError> {
+        self.limiter.acquire().await;
+        self.inner.portforward(namespace, name, port).await
+    }
+
+    async fn exec(
+        &self,
+        namespace: &str,
+        name: &str,
+        command: Vec<String>,
+        params: &AttachParams,
+    ) -> Result<AttachedProcess, KubeError> {
+        self.limiter.acquire().await;
+        self.inner.exec(namespace, name, command, params).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Forwards the ports it was built with, each stream once.
+    struct FakeForwarder {
+        streams: Vec<(u16, tokio::io::DuplexStream)>,
+    }
+
+    impl TakeStream for FakeForwarder {
+        fn take_stream(&mut self, port: u16) -> Option<Box<dyn PortStream>> {
+            let index = self.streams.iter().position(|(p, _)| *p == port)?;
+            Some(Box::new(self.streams.remove(index).1))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forward_connection_takes_once() {
+        let (local, mut remote) = tokio::io::duplex(64);
+        let forwarder = FakeForwarder {
+            streams: vec![(2222, local)],
+        };
+        let mut connection = ForwardConnection::new(forwarder, "k8socks-abc123", 2222);
+
+        let mut stream = connection.take().unwrap();
+        stream.write_all(b"SSH-2.0").await.unwrap();
+        let mut buf = [0u8; 7];
+        remote.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"SSH-2.0");
+
+        let err = connection.take().err().unwrap();
+        assert!(matches!(err, K8sError::PortForwardFailed(_)));
+        assert!(err.to_string().contains("already taken"), "{}", err);
+        assert!(connection.into_inner().streams.is_empty());
+    }
+
+    #[test]
+    fn test_forward_connection_missing_port() {
+        let forwarder = FakeForwarder { streams: Vec::new() };
+        let mut connection = ForwardConnection::new(forwarder, "k8socks-abc123", 2222);
+        let err = connection.take().err().unwrap();
+        assert!(err.to_string().contains("was not forwarded"), "{}", err);
+        // A failed take still consumes the connection.
+        assert!(connection.take().err().unwrap().to_string().contains("already taken"));
+    }
+}
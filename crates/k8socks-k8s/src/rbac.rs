@@ -0,0 +1,149 @@
+//! The RBAC objects that grant a service account exactly what k8socks needs,
+//! for `k8socks rbac` to print. The rules mirror the calls `PodApi` makes;
+//! keep the two in sync.
+
+use k8s_openapi::api::rbac::v1::{ClusterRole, ClusterRoleBinding, PolicyRule, Role, RoleBinding, RoleRef, Subject};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+
+/// The name of the generated role and binding.
+const NAME: &str = "k8socks";
+
+/// `(resource, verbs)` of the core API group, as used in any one namespace.
+const NAMESPACED_RULES: &[(&str, &[&str])] = &[
+    ("pods", &["create", "delete", "get", "list"]),
+    // kube opens port-forwards and execs as WebSocket upgrades of a GET,
+    // which newer API servers also check against `create`.
+    ("pods/portforward", &["create", "get"]),
+    ("pods/exec", &["create", "get"]),
+    ("pods/log", &["get"]),
+    ("events", &["list"]),
+    ("resourcequotas", &["list"]),
+    // The owners of pods deployed with `pod_owner_sentinel`.
+    ("configmaps", &["create", "delete"]),
+];
+
+/// Namespaces are cluster-scoped, so only a ClusterRole can grant them. The
+/// namespace check before a deploy and the per-namespace fallback of
+/// `--all-namespaces` use them; both cope without.
+const CLUSTER_RULES: &[(&str, &[&str])] = &[("namespaces", &["get", "list"])];
+
+/// A Role and RoleBinding for one namespace, or a ClusterRole and
+/// ClusterRoleBinding, which `--all-namespaces` needs.
+pub enum RbacManifests {
+    Namespaced { role: Role, binding: RoleBinding },
+    ClusterWide { role: ClusterRole, binding: ClusterRoleBinding },
+}
+
+fn policy_rules(rules: &[(&str, &[&str])]) -> Vec<PolicyRule> {
+    rules
+        .iter()
+        .map(|(resource, verbs)| PolicyRule {
+            api_groups: Some(vec![String::new()]),
+            resources: Some(vec![resource.to_string()]),
+            verbs: verbs.iter().map(|verb| verb.to_string()).collect(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Builds the objects granting service account `service_account` in
+/// `namespace` what k8socks needs there, or in every namespace when
+/// `cluster_wide` is set.
+pub fn rbac_manifests(namespace: &str, service_account: &str, cluster_wide: bool) -> RbacManifests {
+    let subjects = Some(vec![Subject {
+        kind: "ServiceAccount".to_string(),
+        name: service_account.to_string(),
+        namespace: Some(namespace.to_string()),
+        ..Default::default()
+    }]);
+    if cluster_wide {
+        let mut rules = policy_rules(NAMESPACED_RULES);
+        rules.extend(policy_rules(CLUSTER_RULES));
+        let meta = ObjectMeta {
+            name: Some(NAME.to_string()),
+            ..Default::default()
+        };
+        return RbacManifests::ClusterWide {
+            role: ClusterRole {
+                metadata: meta.clone(),
+                rules: Some(rules),
+                ..Default::default()
+            },
+            binding: ClusterRoleBinding {
+                metadata: meta,
+                role_ref: RoleRef {
+                    api_group: "rbac.authorization.k8s.io".to_string(),
+                    kind: "ClusterRole".to_string(),
+                    name: NAME.to_string(),
+                },
+                subjects,
+            },
+        };
+    }
+    let meta = ObjectMeta {
+        name: Some(NAME.to_string()),
+        namespace: Some(namespace.to_string()),
+        ..Default::default()
+    };
+    RbacManifests::Namespaced {
+        role: Role {
+            metadata: meta.clone(),
+            rules: Some(policy_rules(NAMESPACED_RULES)),
+        },
+        binding: RoleBinding {
+            metadata: meta,
+            role_ref: RoleRef {
+                api_group: "rbac.authorization.k8s.io".to_string(),
+                kind: "Role".to_string(),
+                name: NAME.to_string(),
+            },
+            subjects,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verbs(rules: &[PolicyRule], resource: &str) -> Vec<String> {
+        rules
+            .iter()
+            .find(|rule| rule.resources.as_deref() == Some(&[resource.to_string()][..]))
+            .map(|rule| rule.verbs.clone())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_rbac_manifests_namespaced() {
+        let RbacManifests::Namespaced { role, binding } = rbac_manifests("team-a", "ci", false) else {
+            panic!("expected a Role");
+        };
+        assert_eq!(role.metadata.namespace.as_deref(), Some("team-a"));
+        let rules = role.rules.unwrap();
+        assert_eq!(verbs(&rules, "pods"), ["create", "delete", "get", "list"]);
+        assert_eq!(verbs(&rules, "pods/portforward"), ["create", "get"]);
+        assert_eq!(verbs(&rules, "configmaps"), ["create", "delete"]);
+        assert!(verbs(&rules, "namespaces").is_empty());
+        assert!(rules.iter().all(|rule| rule.api_groups.as_deref() == Some(&[String::new()][..])));
+
+        assert_eq!(binding.metadata.namespace.as_deref(), Some("team-a"));
+        assert_eq!(binding.role_ref.kind, "Role");
+        let subject = &binding.subjects.unwrap()[0];
+        assert_eq!((subject.kind.as_str(), subject.name.as_str()), ("ServiceAccount", "ci"));
+        assert_eq!(subject.namespace.as_deref(), Some("team-a"));
+    }
+
+    #[test]
+    fn test_rbac_manifests_cluster_wide() {
+        let RbacManifests::ClusterWide { role, binding } = rbac_manifests("team-a", "ci", true) else {
+            panic!("expected a ClusterRole");
+        };
+        assert_eq!(role.metadata.namespace, None);
+        let rules = role.rules.unwrap();
+        assert_eq!(verbs(&rules, "pods"), ["create", "delete", "get", "list"]);
+        assert_eq!(verbs(&rules, "namespaces"), ["get", "list"]);
+        assert_eq!(binding.role_ref.kind, "ClusterRole");
+        assert_eq!(binding.subjects.unwrap()[0].namespace.as_deref(), Some("team-a"));
+    }
+}
@@ -0,0 +1,56 @@
+//! End-to-end check that the default configuration yields a pod we can SSH into.
+//!
+//! Requires a reachable Kubernetes cluster plus local `ssh` and `ssh-keygen`
+//! binaries, so it is ignored by default. Run it with
+//! `cargo test -p k8socks-k8s --test default_image -- --ignored`.
+
+use std::process::Command;
+use std::time::Duration;
+
+use k8socks_k8s::{wait_for_sshd, K8sServiceImpl};
+use k8socks_traits::config::Config;
+use k8socks_traits::k8s::K8sService;
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "requires a Kubernetes cluster and local ssh/ssh-keygen"]
+async fn test_default_config_pod_accepts_ssh() {
+    let key_dir = std::env::temp_dir().join(format!("k8socks-it-{}", std::process::id()));
+    std::fs::create_dir_all(&key_dir).unwrap();
+    let key_path = key_dir.join("id_ed25519");
+    let status = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", ""])
+        .arg("-f")
+        .arg(&key_path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let config = Config {
+        ssh_public_key_path: Some(format!("{}.pub", key_path.display())),
+        ..Default::default()
+    };
+    let k8s_service = K8sServiceImpl::new(&config).await.unwrap();
+    let pod_ref = k8s_service.deploy_pod().await.unwrap();
+
+    let result = async {
+        k8s_service.wait_for_pod_ready(&pod_ref).await?;
+        let pf_handle = k8s_service.port_forward(&pod_ref, 0).await?;
+        wait_for_sshd(pf_handle.local_port, 30, Duration::from_secs(1)).await?;
+        let status = Command::new("ssh")
+            .arg("-i")
+            .arg(&key_path)
+            .args(["-o", "StrictHostKeyChecking=no", "-o", "UserKnownHostsFile=/dev/null"])
+            .args(["-o", "BatchMode=yes", "-p"])
+            .arg(pf_handle.local_port.to_string())
+            .arg(format!("{}@127.0.0.1", config.ssh_username.as_ref().unwrap()))
+            .arg("true")
+            .status()?;
+        Ok::<_, Box<dyn std::error::Error>>(status.success())
+    }
+    .await;
+
+    k8s_service.delete_pod(&pod_ref, Some(0)).await.unwrap();
+    std::fs::remove_dir_all(&key_dir).ok();
+
+    assert!(result.unwrap(), "ssh into the default pod failed");
+}
@@ -2,13 +2,16 @@ use colored::*;
 use std::str::FromStr;
 use tracing::Level;
 use tracing_subscriber::fmt::format::{FormatEvent, FormatFields, Writer};
-use tracing_subscriber::fmt::{FmtContext, Layer};
+use tracing_subscriber::fmt::{FmtContext, FormattedFields, Layer};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::EnvFilter;
 use k8socks_traits::logging::LoggingService;
 
 /// A custom event formatter that produces logs in the desired format.
+///
+/// Fields of the spans an event happens in, e.g. the `session_id` and `pod`
+/// of the `session` span, are rendered in brackets before the message.
 struct CustomFormatter {
     use_color: bool,
 }
@@ -47,12 +50,38 @@ where
 
         write!(writer, "{} {} ", time, level_str)?;
 
+        if let Some(scope) = ctx.event_scope() {
+            let fields: Vec<String> = scope
+                .from_root()
+                .filter_map(|span| {
+                    let extensions = span.extensions();
+                    let fields = extensions.get::<FormattedFields<N>>()?;
+                    (!fields.is_empty()).then(|| fields.to_string())
+                })
+                .collect();
+            if !fields.is_empty() {
+                write!(writer, "[{}] ", fields.join(" "))?;
+            }
+        }
+
         ctx.format_fields(writer.by_ref(), event)?;
 
         writeln!(writer)
     }
 }
 
+/// The level to log at: `-q` keeps only warnings and errors, `-v` logs at
+/// `debug` and `-vv` (or more) at `trace`; otherwise the configured level.
+/// `-q` wins over `-v`.
+pub fn effective_log_level(log_level: &str, verbose: u8, quiet: bool) -> &str {
+    match verbose {
+        _ if quiet => "warn",
+        0 => log_level,
+        1 => "debug",
+        _ => "trace",
+    }
+}
+
 pub struct LoggingServiceImpl;
 
 impl LoggingService for LoggingServiceImpl {
@@ -68,7 +97,9 @@ impl LoggingService for LoggingServiceImpl {
 
         let formatter = CustomFormatter { use_color };
 
-        let layer = Layer::default().event_format(formatter);
+        // Span fields are formatted by the layer, which would add its own
+        // colors even with `--no-color`.
+        let layer = Layer::default().with_ansi(use_color).event_format(formatter);
 
         tracing_subscriber::registry()
             .with(env_filter)
@@ -77,4 +108,70 @@ impl LoggingService for LoggingServiceImpl {
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capture(f: impl FnOnce()) -> String {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let layer = Layer::default()
+            .with_ansi(false)
+            .event_format(CustomFormatter { use_color: false })
+            .with_writer(move || writer.clone());
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, f);
+        let output = buffer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_formatter_includes_span_fields() {
+        let output = capture(|| {
+            let span = tracing::info_span!("session", session_id = %"abc123", pod = tracing::field::Empty);
+            let _guard = span.enter();
+            tracing::info!("before the pod exists");
+            span.record("pod", tracing::field::display("k8socks-xyz"));
+            tracing::info!("pod created");
+        });
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(" INFO [session_id=abc123] before the pod exists"), "{}", lines[0]);
+        assert!(lines[1].ends_with(" INFO [session_id=abc123 pod=k8socks-xyz] pod created"), "{}", lines[1]);
+    }
+
+    #[test]
+    fn test_effective_log_level() {
+        assert_eq!(effective_log_level("info", 0, false), "info");
+        assert_eq!(effective_log_level("error", 0, false), "error");
+        assert_eq!(effective_log_level("info", 1, false), "debug");
+        assert_eq!(effective_log_level("warn", 2, false), "trace");
+        assert_eq!(effective_log_level("info", 5, false), "trace");
+        assert_eq!(effective_log_level("debug", 0, true), "warn");
+        assert_eq!(effective_log_level("info", 2, true), "warn");
+    }
+
+    #[test]
+    fn test_formatter_without_span_has_no_prefix() {
+        let output = capture(|| tracing::warn!("no session"));
+        assert!(output.trim_end().ends_with(" WARN no session"), "{}", output);
+    }
+}
@@ -0,0 +1,110 @@
+//! A minimal HTTP endpoint for liveness checks of the k8socks process, for
+//! running it under Kubernetes or a process supervisor.
+//!
+//! It answers `GET /healthz` with `200 OK` while the proxy works and
+//! `503 Service Unavailable` otherwise; anything else gets `404 Not Found`.
+//! Requests are served one at a time, which is plenty for a prober.
+
+use std::future::Future;
+use std::time::Duration;
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::debug;
+
+/// Listens on `127.0.0.1:<port>` (`0` picks a free port).
+pub async fn bind(port: u16) -> io::Result<TcpListener> {
+    TcpListener::bind(("127.0.0.1", port)).await
+}
+
+/// Serves health checks on `listener`, asking `check` for the current state
+/// on every `/healthz` request. Runs until the returned future is dropped.
+pub async fn serve<F, Fut>(listener: TcpListener, check: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    loop {
+        let (client, addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                debug!("Failed to accept health check connection: {}", e);
+                continue;
+            }
+        };
+        // A client that never finishes its request must not block the others.
+        let served = tokio::time::timeout(Duration::from_secs(5), respond(client, &check)).await;
+        if let Ok(Err(e)) = served {
+            debug!("Health check from {} failed: {}", addr, e);
+        }
+    }
+}
+
+async fn respond<F, Fut>(client: TcpStream, check: &F) -> io::Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let mut client = BufReader::new(client);
+    let mut request_line = String::new();
+    client.read_line(&mut request_line).await?;
+    // Skip the headers.
+    let mut header = String::new();
+    while client.read_line(&mut header).await? > 0 && header.trim_end() != "" {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let status = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/healthz")) if check().await => "200 OK",
+        (Some("GET"), Some("/healthz")) => "503 Service Unavailable",
+        _ => "404 Not Found",
+    };
+    let body = format!("{}\n", status);
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    client.get_mut().write_all(response.as_bytes()).await?;
+    client.get_mut().shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use tokio::io::AsyncReadExt;
+
+    async fn get(port: u16, path: &str) -> String {
+        let mut client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_healthz_reports_check() {
+        let listener = bind(0).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let healthy = Arc::new(AtomicBool::new(true));
+        let state = healthy.clone();
+        let server = tokio::spawn(serve(listener, move || {
+            let healthy = state.load(Ordering::SeqCst);
+            async move { healthy }
+        }));
+
+        assert_eq!(get(port, "/healthz").await, "HTTP/1.1 200 OK");
+        healthy.store(false, Ordering::SeqCst);
+        assert_eq!(get(port, "/healthz").await, "HTTP/1.1 503 Service Unavailable");
+        assert_eq!(get(port, "/metrics").await, "HTTP/1.1 404 Not Found");
+
+        // Dropping the server closes the port.
+        server.abort();
+        let _ = server.await;
+        assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+    }
+}
@@ -0,0 +1,235 @@
+//! An HTTP CONNECT front end for the SOCKS listener of `ssh -D`.
+//!
+//! Some tools only speak HTTP proxies. For those, the relay accepts
+//! `CONNECT host:port` requests and opens the tunnel through `ssh` with a
+//! SOCKS5 handshake, so the traffic takes the same path as in SOCKS mode.
+
+use tokio::io::{self, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// Upper bound on the size of a CONNECT request head.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// Parses the request line of a CONNECT request into host and port.
+fn parse_connect_line(line: &str) -> Result<(String, u16), &'static str> {
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err("malformed request line");
+    };
+    if !method.eq_ignore_ascii_case("CONNECT") {
+        return Err("only CONNECT is supported");
+    }
+    if !version.starts_with("HTTP/1.") {
+        return Err("unsupported HTTP version");
+    }
+    let (host, port) = target.rsplit_once(':').ok_or("CONNECT target needs a port")?;
+    let port = port.parse().map_err(|_| "invalid port")?;
+    // IPv6 literals come in brackets, e.g. `[::1]:443`.
+    let host = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    if host.is_empty() || host.len() > 255 {
+        return Err("invalid host");
+    }
+    Ok((host.to_string(), port))
+}
+
+/// Reads the request head and returns its request line. The headers are read
+/// and discarded.
+async fn read_request_line<R: AsyncBufReadExt + Unpin>(client: &mut R) -> io::Result<String> {
+    let mut request_line = String::new();
+    let mut total = client.read_line(&mut request_line).await?;
+    loop {
+        let mut header = String::new();
+        let n = client.read_line(&mut header).await?;
+        total += n;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "request head ended early"));
+        }
+        if total > MAX_REQUEST_HEAD {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request head too large"));
+        }
+        if header == "\r\n" || header == "\n" {
+            break;
+        }
+    }
+    Ok(request_line.trim_end().to_string())
+}
+
+/// Asks the SOCKS5 server on `upstream` to connect to `host:port`.
+pub async fn socks5_connect(upstream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+    // Version 5, one auth method: no authentication.
+    upstream.write_all(&[5, 1, 0]).await?;
+    let mut choice = [0u8; 2];
+    upstream.read_exact(&mut choice).await?;
+    if choice != [5, 0] {
+        return Err(io::Error::other("SOCKS server refused the handshake"));
+    }
+
+    // CONNECT by domain name; ssh resolves it on the far side.
+    let mut request = vec![5, 1, 0, 3, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    upstream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    upstream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(io::Error::other(format!("SOCKS connect failed with code {}", reply[1])));
+    }
+    // Skip the bound address and port.
+    let address_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => upstream.read_u8().await? as usize,
+        _ => return Err(io::Error::other("invalid SOCKS address type")),
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    upstream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// Serves one HTTP proxy client, tunnelling it through the SOCKS listener on
+/// `upstream_port`.
+pub(crate) async fn serve_connection(client: TcpStream, upstream_port: u16) -> io::Result<()> {
+    let mut client = BufReader::new(client);
+    let request_line = read_request_line(&mut client).await?;
+    let (host, port) = match parse_connect_line(&request_line) {
+        Ok(target) => target,
+        Err(reason) => {
+            let status: &[u8] = if request_line.to_ascii_uppercase().starts_with("CONNECT ") {
+                b"HTTP/1.1 400 Bad Request\r\n\r\n"
+            } else {
+                b"HTTP/1.1 405 Method Not Allowed\r\nAllow: CONNECT\r\n\r\n"
+            };
+            let _ = client.get_mut().write_all(status).await;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, reason));
+        }
+    };
+
+    let tunnel = async {
+        let mut upstream = TcpStream::connect(("127.0.0.1", upstream_port)).await?;
+        socks5_connect(&mut upstream, &host, port).await?;
+        Ok::<_, io::Error>(upstream)
+    };
+    let mut upstream = match tunnel.await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            let _ = client.get_mut().write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").await;
+            return Err(e);
+        }
+    };
+    client
+        .get_mut()
+        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+        .await?;
+    // Anything the client sent after the head is still buffered in `client`.
+    io::copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse_connect_line() {
+        assert_eq!(
+            parse_connect_line("CONNECT example.com:443 HTTP/1.1"),
+            Ok(("example.com".to_string(), 443))
+        );
+        assert_eq!(
+            parse_connect_line("connect [::1]:8443 HTTP/1.0"),
+            Ok(("::1".to_string(), 8443))
+        );
+        assert!(parse_connect_line("GET http://example.com/ HTTP/1.1").is_err());
+        assert!(parse_connect_line("CONNECT example.com HTTP/1.1").is_err());
+        assert!(parse_connect_line("CONNECT example.com:https HTTP/1.1").is_err());
+        assert!(parse_connect_line("CONNECT example.com:443").is_err());
+    }
+
+    /// A SOCKS5 server that accepts one CONNECT, reports the requested target
+    /// and then echoes. Connects to `refused.example` fail.
+    async fn socks5_upstream() -> (u16, tokio::sync::oneshot::Receiver<(String, u16)>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            conn.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            conn.write_all(&[5, 0]).await.unwrap();
+
+            let mut head = [0u8; 5];
+            conn.read_exact(&mut head).await.unwrap();
+            assert_eq!(head[..4], [5, 1, 0, 3]);
+            let mut host = vec![0u8; head[4] as usize];
+            conn.read_exact(&mut host).await.unwrap();
+            let port = conn.read_u16().await.unwrap();
+            let host = String::from_utf8(host).unwrap();
+
+            let code = if host == "refused.example" { 5 } else { 0 };
+            conn.write_all(&[5, code, 0, 1, 127, 0, 0, 1, 0, 0]).await.unwrap();
+            tx.send((host, port)).unwrap();
+            let (mut reader, mut writer) = conn.split();
+            let _ = io::copy(&mut reader, &mut writer).await;
+        });
+        (port, rx)
+    }
+
+    async fn proxy(upstream_port: u16) -> u16 {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (conn, _) = listener.accept().await.unwrap();
+            let _ = serve_connection(conn, upstream_port).await;
+        });
+        port
+    }
+
+    async fn read_response_head(client: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(client.read_u8().await.unwrap());
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_connect_tunnels_through_socks() {
+        let (upstream_port, target) = socks5_upstream().await;
+        let mut client = TcpStream::connect(("127.0.0.1", proxy(upstream_port).await)).await.unwrap();
+
+        // Data sent right behind the head must not get lost.
+        client
+            .write_all(b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\nping")
+            .await
+            .unwrap();
+        assert!(read_response_head(&mut client).await.starts_with("HTTP/1.1 200"));
+        assert_eq!(target.await.unwrap(), ("example.com".to_string(), 443));
+
+        let mut buf = [0u8; 4];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[tokio::test]
+    async fn test_failed_socks_connect_is_bad_gateway() {
+        let (upstream_port, _target) = socks5_upstream().await;
+        let mut client = TcpStream::connect(("127.0.0.1", proxy(upstream_port).await)).await.unwrap();
+        client.write_all(b"CONNECT refused.example:80 HTTP/1.1\r\n\r\n").await.unwrap();
+        assert!(read_response_head(&mut client).await.starts_with("HTTP/1.1 502"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_requests_are_rejected() {
+        let (upstream_port, _target) = socks5_upstream().await;
+        let mut client = TcpStream::connect(("127.0.0.1", proxy(upstream_port).await)).await.unwrap();
+        client.write_all(b"GET http://example.com/ HTTP/1.1\r\n\r\n").await.unwrap();
+        assert!(read_response_head(&mut client).await.starts_with("HTTP/1.1 405"));
+
+        let mut client = TcpStream::connect(("127.0.0.1", proxy(upstream_port).await)).await.unwrap();
+        client.write_all(b"CONNECT example.com HTTP/1.1\r\n\r\n").await.unwrap();
+        assert!(read_response_head(&mut client).await.starts_with("HTTP/1.1 400"));
+    }
+}
@@ -1,8 +1,18 @@
-use std::process::Stdio;
+pub mod health;
+mod http_connect;
+pub mod orphans;
+pub mod relay;
+
+pub use http_connect::socks5_connect;
+
+use std::fmt::Display;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
 use async_trait::async_trait;
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::{Command};
-use tracing::{error, info, warn};
+use tokio::process::{Child, Command};
+use tokio::sync::oneshot;
+use tracing::{debug, error, info, warn, Instrument, Level};
 
 use k8socks_traits::config::Config;
 use k8socks_traits::ssh::{SshError, SshProcessHandle, SshService};
@@ -11,6 +21,158 @@ pub struct SshServiceImpl {
     config: Config,
 }
 
+/// How long ssh gets to exit after SIGTERM before it is killed.
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Passed as `-o SetEnv=...` so that k8socks' own ssh processes can be told
+/// apart from a user's tunnels with the same options.
+pub(crate) const SSH_MARKER: &str = "SetEnv=K8SOCKS=1";
+
+/// Fragments (lowercase) of ssh client messages that report a failure. Kept
+/// specific: broad words such as "error" or "failed" also show up in routine
+/// messages, e.g. about a single SOCKS channel.
+const SSH_ERROR_MARKERS: &[&str] = &["permission denied", "connection refused"];
+
+/// Maps a line of ssh stderr output to the level it is logged at: `-v`
+/// chatter is debug, failures are errors, warnings stay warnings and
+/// anything else is informational.
+fn classify_ssh_line(line: &str) -> Level {
+    if line.starts_with("debug") {
+        return Level::DEBUG;
+    }
+    let line = line.to_lowercase();
+    if SSH_ERROR_MARKERS.iter().any(|marker| line.contains(marker)) {
+        Level::ERROR
+    } else if line.starts_with("warning") {
+        Level::WARN
+    } else {
+        Level::INFO
+    }
+}
+
+/// Extracts the destination of a proxied connection from the line `ssh -vv`
+/// logs when a client opens one through the SOCKS listener, e.g.
+/// `debug2: channel 3: dynamic request: socks5 host example.com port 443 command 1`.
+fn parse_connection_line(line: &str) -> Option<(String, u16)> {
+    let (_, request) = line.split_once(": dynamic request: ")?;
+    let mut words = request.split_whitespace();
+    let _protocol = words.next()?;
+    let (Some("host"), Some(host), Some("port"), Some(port)) = (words.next(), words.next(), words.next(), words.next())
+    else {
+        return None;
+    };
+    Some((host.to_string(), port.parse().ok()?))
+}
+
+/// Asks ssh to exit with SIGTERM, so that it closes its connection to the
+/// pod itself, and kills it if it has not done so after `STOP_TIMEOUT`.
+async fn stop_child(child: &mut Child) -> std::io::Result<ExitStatus> {
+    if let Some(pid) = child.id() {
+        match orphans::terminate(pid) {
+            Ok(()) => match tokio::time::timeout(STOP_TIMEOUT, child.wait()).await {
+                Ok(status) => return status,
+                Err(_) => warn!("SSH process did not exit within {}s; killing it", STOP_TIMEOUT.as_secs()),
+            },
+            Err(e) => debug!("Failed to ask the SSH process to exit: {}", e),
+        }
+    }
+    child.kill().await?;
+    child.wait().await
+}
+
+/// Asks the OS for a free local port by binding a throwaway listener.
+///
+/// The listener is closed before `ssh` binds the port, so another process can
+/// grab it in between. Callers keep that window small by picking the port
+/// immediately before spawning `ssh`, which then fails loudly if it lost the race.
+pub fn pick_free_port() -> std::io::Result<u16> {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0))?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// Builds the full `ssh` command line for the SOCKS proxy, binary first.
+///
+/// The ports are `Display` so that a dry-run can render placeholders for
+/// ports that are only known once the proxy is actually started.
+pub fn build_ssh_command(
+    config: &Config,
+    local_socks_port: impl Display,
+    forwarded_ssh_port: impl Display,
+) -> Vec<String> {
+    let ssh_binary = config.ssh_binary_path.as_deref().unwrap_or("ssh");
+    let mut command = vec![ssh_binary.to_string()];
+    command.extend(build_ssh_args(config, local_socks_port, forwarded_ssh_port));
+    command
+}
+
+/// Builds the arguments passed to the `ssh` client for the SOCKS proxy.
+fn build_ssh_args(
+    config: &Config,
+    local_socks_port: impl Display,
+    forwarded_ssh_port: impl Display,
+) -> Vec<String> {
+    let ssh_username = config.ssh_username.as_ref().unwrap();
+
+    let mut args = Vec::new();
+    // Without a config file of its own, `-F none` keeps the user's
+    // `~/.ssh/config` (a `Host *` section with a `ProxyCommand`, say) out of
+    // the connection to the forwarded port. A jump host is often an alias
+    // defined there, so the file is left in effect then.
+    match &config.ssh_config_file {
+        Some(file) => args.extend(["-F".to_string(), file.clone()]),
+        None if config.ssh_jump_host.is_none() => args.extend(["-F".to_string(), "none".to_string()]),
+        None => {}
+    }
+    args.extend([
+        "-o".to_string(),
+        "StrictHostKeyChecking=no".to_string(),
+        // Exit instead of running without a proxy if the SOCKS port is taken.
+        "-o".to_string(),
+        "ExitOnForwardFailure=yes".to_string(),
+        "-o".to_string(),
+        SSH_MARKER.to_string(),
+    ]);
+    // Bound the handshake so an unresponsive sshd fails fast instead of hanging.
+    if let Some(timeout) = config.ssh_connect_timeout_seconds {
+        args.push("-o".to_string());
+        args.push(format!("ConnectTimeout={}", timeout));
+    }
+    // For sshd images or policies that only allow specific algorithms.
+    if let Some(ciphers) = &config.ssh_ciphers {
+        args.push("-o".to_string());
+        args.push(format!("Ciphers={}", ciphers));
+    }
+    if let Some(kex) = &config.ssh_kex_algorithms {
+        args.push("-o".to_string());
+        args.push(format!("KexAlgorithms={}", kex));
+    }
+    // With a jump host, the target host below is dialed from the (last) jump host.
+    if let Some(jump) = &config.ssh_jump_host {
+        args.push("-J".to_string());
+        args.push(jump.clone());
+    }
+    if config.ssh_forward_agent == Some(true) {
+        args.push("-A".to_string());
+    }
+    let mut verbosity = config.ssh_verbosity.unwrap_or(0).min(3);
+    // ssh only reports SOCKS destinations at `-vv`.
+    if config.log_connections == Some(true) {
+        verbosity = verbosity.max(2);
+    }
+    if verbosity > 0 {
+        args.push(format!("-{}", "v".repeat(verbosity.into())));
+    }
+    args.extend([
+        "-N".to_string(), // Do not execute a remote command
+        "-D".to_string(),
+        local_socks_port.to_string(),
+        "-p".to_string(),
+        forwarded_ssh_port.to_string(),
+        format!("{}@{}", ssh_username, config.ssh_target_host.as_deref().unwrap_or("127.0.0.1")),
+    ]);
+    args
+}
+
 #[async_trait]
 impl SshService for SshServiceImpl {
     fn new(config: &Config) -> Self {
@@ -23,45 +185,50 @@ impl SshService for SshServiceImpl {
         &self,
         forwarded_ssh_port: u16,
     ) -> Result<SshProcessHandle, SshError> {
-        let local_socks_port = self.config.local_socks_port.unwrap_or(1080);
-        let ssh_username = self.config.ssh_username.as_ref().unwrap();
-
-        let mut cmd = Command::new("ssh");
-        cmd.arg("-o")
-            .arg("StrictHostKeyChecking=no")
-            .arg("-v") // Add verbosity to get connection logs
-            .arg("-N") // Do not execute a remote command
-            .arg("-D")
-            .arg(local_socks_port.to_string())
-            .arg("-p")
-            .arg(forwarded_ssh_port.to_string())
-            .arg(format!("{}@127.0.0.1", ssh_username));
+        let socks_port = match self.config.local_socks_port.unwrap_or(1080) {
+            0 => pick_free_port()?,
+            port => port,
+        };
+
+        if self.config.ssh_forward_agent == Some(true) {
+            warn!(
+                "SSH agent forwarding is on: anyone with root in the pod or on its node can use \
+                 your agent to authenticate as you while the session runs."
+            );
+        }
+
+        let command = build_ssh_command(&self.config, socks_port, forwarded_ssh_port);
+        let ssh_binary = &command[0];
+        let mut cmd = Command::new(ssh_binary);
+        cmd.args(&command[1..]);
 
         // Pipe stdout and stderr to capture them
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
+        // Keep ssh out of the terminal's process group so Ctrl+C only reaches
+        // k8socks, which decides when ssh stops; dropping the handle kills it.
+        #[cfg(unix)]
+        cmd.process_group(0);
+        cmd.kill_on_drop(true);
 
         info!("Spawning SSH command: {:?}", cmd);
 
-        let child = cmd.spawn()?;
+        let child = cmd.spawn().map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => SshError::SshBinaryNotFound(ssh_binary.to_string()),
+            _ => SshError::ProcessError(e),
+        })?;
 
-        Ok(SshProcessHandle { child })
+        Ok(SshProcessHandle { child, socks_port })
     }
 
-    async fn watch(&self, handle: SshProcessHandle) -> Result<(), SshError> {
+    async fn watch(&self, handle: SshProcessHandle, mut stop: oneshot::Receiver<()>) -> Result<(), SshError> {
         let mut child = handle.child;
         let stdout = child.stdout.take().ok_or_else(|| {
-            SshError::ProcessError(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to capture stdout",
-            ))
+            SshError::ProcessError(std::io::Error::other("Failed to capture stdout"))
         })?;
 
         let stderr = child.stderr.take().ok_or_else(|| {
-            SshError::ProcessError(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to capture stderr",
-            ))
+            SshError::ProcessError(std::io::Error::other("Failed to capture stderr"))
         })?;
 
         let mut stdout_reader = BufReader::new(stdout).lines();
@@ -71,21 +238,42 @@ impl SshService for SshServiceImpl {
             while let Ok(Some(line)) = stdout_reader.next_line().await {
                 info!("[ssh] {}", line);
             }
-        });
+        }.in_current_span());
 
+        // ssh writes its `-v` output to stderr; when verbosity was requested
+        // explicitly, show it without also requiring a debug log level.
+        let verbose = self.config.ssh_verbosity.unwrap_or(0) > 0;
+        let log_connections = self.config.log_connections == Some(true);
         let stderr_task = tokio::spawn(async move {
             while let Ok(Some(line)) = stderr_reader.next_line().await {
-                warn!("[ssh] {}", line);
+                if log_connections && let Some((host, port)) = parse_connection_line(&line) {
+                    info!("Connection to {}:{}", host, port);
+                }
+                match classify_ssh_line(&line) {
+                    Level::ERROR => error!("[ssh] {}", line),
+                    Level::WARN => warn!("[ssh] {}", line),
+                    Level::DEBUG if !verbose => debug!("[ssh] {}", line),
+                    _ => info!("[ssh] {}", line),
+                }
             }
-        });
+        }.in_current_span());
 
-        let status = child.wait().await?;
+        let (status, stopped) = tokio::select! {
+            status = child.wait() => (status?, false),
+            Ok(()) = &mut stop => {
+                debug!("Stopping the SSH process...");
+                (stop_child(&mut child).await?, true)
+            }
+        };
 
         // Wait for the logging tasks to finish to ensure all output is captured.
         stdout_task.await.ok();
         stderr_task.await.ok();
 
-        if status.success() {
+        if stopped {
+            info!("SSH process stopped.");
+            Ok(())
+        } else if status.success() {
             info!("SSH process exited gracefully.");
             Ok(())
         } else {
@@ -93,4 +281,240 @@ impl SshService for SshServiceImpl {
             Err(SshError::UnexpectedExit)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_missing_ssh_binary_is_mapped() {
+        let config = Config {
+            ssh_binary_path: Some("/nonexistent/k8socks-test-ssh".to_string()),
+            ..Default::default()
+        };
+        let ssh_service = SshServiceImpl::new(&config);
+
+        let result = ssh_service.start_socks_proxy(2222).await;
+
+        assert!(matches!(
+            result,
+            Err(SshError::SshBinaryNotFound(path)) if path == "/nonexistent/k8socks-test-ssh"
+        ));
+    }
+
+    #[test]
+    fn test_pick_free_port() {
+        let port = pick_free_port().unwrap();
+        assert_ne!(port, 0);
+        // The throwaway listener is gone, so the port can be bound again.
+        assert!(std::net::TcpListener::bind(("127.0.0.1", port)).is_ok());
+    }
+
+    #[test]
+    fn test_connect_timeout_option() {
+        let config = Config {
+            ssh_connect_timeout_seconds: Some(5),
+            ..Default::default()
+        };
+        let args = build_ssh_args(&config, 1080, 2222);
+        assert!(args.windows(2).any(|w| w == ["-o", "ConnectTimeout=5"]));
+
+        let config = Config {
+            ssh_connect_timeout_seconds: None,
+            ..Default::default()
+        };
+        let args = build_ssh_args(&config, 1080, 2222);
+        assert!(!args.iter().any(|a| a.starts_with("ConnectTimeout=")));
+    }
+
+    #[test]
+    fn test_algorithm_options() {
+        let args = build_ssh_args(&Config::default(), 1080, 2222);
+        assert!(!args.iter().any(|a| a.starts_with("Ciphers=") || a.starts_with("KexAlgorithms=")));
+
+        let config = Config {
+            ssh_ciphers: Some("aes256-gcm@openssh.com,aes256-ctr".to_string()),
+            ssh_kex_algorithms: Some("curve25519-sha256".to_string()),
+            ..Default::default()
+        };
+        let args = build_ssh_args(&config, 1080, 2222);
+        assert!(args.windows(2).any(|w| w == ["-o", "Ciphers=aes256-gcm@openssh.com,aes256-ctr"]));
+        assert!(args.windows(2).any(|w| w == ["-o", "KexAlgorithms=curve25519-sha256"]));
+    }
+
+    #[test]
+    fn test_build_ssh_command() {
+        let config = Config {
+            ssh_binary_path: Some("/usr/local/bin/ssh".to_string()),
+            ssh_connect_timeout_seconds: None,
+            ..Default::default()
+        };
+        assert_eq!(
+            build_ssh_command(&config, 1081, 40123),
+            [
+                "/usr/local/bin/ssh",
+                "-F",
+                "none",
+                "-o",
+                "StrictHostKeyChecking=no",
+                "-o",
+                "ExitOnForwardFailure=yes",
+                "-o",
+                "SetEnv=K8SOCKS=1",
+                "-N",
+                "-D",
+                "1081",
+                "-p",
+                "40123",
+                "k8socks@127.0.0.1",
+            ]
+        );
+
+        // Placeholders render in place of ports that are not known yet.
+        let command = build_ssh_command(&Config::default(), "<socks-port>", "<forwarded-port>");
+        assert_eq!(command[0], "ssh");
+        assert!(command.windows(2).any(|w| w == ["-p", "<forwarded-port>"]));
+    }
+
+    #[test]
+    fn test_ssh_config_file() {
+        let args = build_ssh_args(&Config::default(), 1080, 2222);
+        assert_eq!(args[..2], ["-F", "none"]);
+
+        let config = Config {
+            ssh_config_file: Some("/home/me/.ssh/k8socks_config".to_string()),
+            ..Default::default()
+        };
+        let args = build_ssh_args(&config, 1080, 2222);
+        assert_eq!(args[..2], ["-F", "/home/me/.ssh/k8socks_config"]);
+        assert_eq!(args.iter().filter(|a| *a == "-F").count(), 1);
+
+        // A jump host may be an alias from ~/.ssh/config, which must stay in effect.
+        let config = Config {
+            ssh_jump_host: Some("bastion".to_string()),
+            ..Default::default()
+        };
+        let args = build_ssh_args(&config, 1080, 2222);
+        assert!(!args.contains(&"-F".to_string()));
+        assert!(args.windows(2).any(|w| w == ["-J", "bastion"]));
+
+        // An explicit file is passed either way.
+        let config = Config {
+            ssh_jump_host: Some("bastion".to_string()),
+            ssh_config_file: Some("/home/me/.ssh/k8socks_config".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(build_ssh_args(&config, 1080, 2222)[..2], ["-F", "/home/me/.ssh/k8socks_config"]);
+    }
+
+    #[test]
+    fn test_jump_host() {
+        let args = build_ssh_args(&Config::default(), 1080, 2222);
+        assert!(!args.contains(&"-J".to_string()));
+
+        let config = Config {
+            ssh_jump_host: Some("me@bastion:2222".to_string()),
+            ..Default::default()
+        };
+        let args = build_ssh_args(&config, 1080, 2222);
+        let jump = args.iter().position(|a| a == "-J").unwrap();
+        assert_eq!(args[jump + 1], "me@bastion:2222");
+        // The jump must come before the destination.
+        assert!(jump < args.iter().position(|a| a.ends_with("@127.0.0.1")).unwrap());
+    }
+
+    #[test]
+    fn test_ssh_target_host() {
+        let args = build_ssh_args(&Config::default(), 1080, 2222);
+        assert_eq!(args.last().unwrap(), "k8socks@127.0.0.1");
+
+        let config = Config {
+            ssh_target_host: Some("tunnel.internal".to_string()),
+            ..Default::default()
+        };
+        let args = build_ssh_args(&config, 1080, 2222);
+        assert_eq!(args.last().unwrap(), "k8socks@tunnel.internal");
+    }
+
+    #[test]
+    fn test_forward_agent() {
+        let args = build_ssh_args(&Config::default(), 1080, 2222);
+        assert!(!args.contains(&"-A".to_string()));
+
+        let config = Config {
+            ssh_forward_agent: Some(true),
+            ..Default::default()
+        };
+        let args = build_ssh_args(&config, 1080, 2222);
+        assert!(args.contains(&"-A".to_string()));
+    }
+
+    #[test]
+    fn test_ssh_verbosity() {
+        let args = build_ssh_args(&Config::default(), 1080, 2222);
+        assert!(!args.iter().any(|a| a.starts_with("-v")));
+
+        let config = Config {
+            ssh_verbosity: Some(2),
+            ..Default::default()
+        };
+        let args = build_ssh_args(&config, 1080, 2222);
+        assert!(args.contains(&"-vv".to_string()));
+
+        let config = Config {
+            ssh_verbosity: Some(9),
+            ..Default::default()
+        };
+        let args = build_ssh_args(&config, 1080, 2222);
+        assert!(args.contains(&"-vvv".to_string()));
+
+        // Connection logging needs at least `-vv`.
+        let config = Config {
+            log_connections: Some(true),
+            ..Default::default()
+        };
+        let args = build_ssh_args(&config, 1080, 2222);
+        assert!(args.contains(&"-vv".to_string()));
+    }
+
+    #[test]
+    fn test_parse_connection_line() {
+        assert_eq!(
+            parse_connection_line("debug2: channel 3: dynamic request: socks5 host example.com port 443 command 1"),
+            Some(("example.com".to_string(), 443))
+        );
+        assert_eq!(
+            parse_connection_line("debug2: channel 4: dynamic request: socks4 host 10.0.0.7 port 5432 command 1"),
+            Some(("10.0.0.7".to_string(), 5432))
+        );
+        assert_eq!(parse_connection_line("debug1: channel 3: new [dynamic-tcpip]"), None);
+        assert_eq!(
+            parse_connection_line("debug1: Connection to port 1080 forwarding to socks port 0 requested."),
+            None
+        );
+        assert_eq!(parse_connection_line("debug2: channel 3: dynamic request: socks5 host x port http"), None);
+    }
+
+    #[test]
+    fn test_classify_ssh_line() {
+        let cases = [
+            ("debug1: Connecting to 127.0.0.1 [127.0.0.1] port 40123.", Level::DEBUG),
+            ("debug1: channel 2: open failed: connect failed: Connection refused", Level::DEBUG),
+            ("debug2: channel 0: request keepalive@openssh.com confirm 1", Level::DEBUG),
+            ("k8socks@127.0.0.1: Permission denied (publickey).", Level::ERROR),
+            ("ssh: connect to host 127.0.0.1 port 40123: Connection refused", Level::ERROR),
+            ("kex_exchange_identification: Connection closed by remote host", Level::INFO),
+            ("channel 3: open failed: administratively prohibited: open failed", Level::INFO),
+            (
+                "Warning: Permanently added '[127.0.0.1]:40123' (ED25519) to the list of known hosts.",
+                Level::WARN,
+            ),
+            ("Authenticated to 127.0.0.1 ([127.0.0.1]:40123) using \"publickey\".", Level::INFO),
+            ("Transferred: sent 3140, received 2876 bytes, in 12.3 seconds", Level::INFO),
+        ];
+        for (line, level) in cases {
+            assert_eq!(classify_ssh_line(line), level, "{}", line);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Finding `ssh` processes that an earlier k8socks run left behind, e.g. when
+//! it was killed before it could stop its child. Such a process keeps its
+//! SOCKS port bound until it is stopped.
+//!
+//! This is best effort: the process table is read from `/proc` on Linux and
+//! from `ps` on other Unix systems. Elsewhere nothing is found.
+
+use std::io;
+use std::process::Command;
+
+/// An `ssh` process with the arguments k8socks starts it with, whose k8socks
+/// parent is gone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OrphanedSsh {
+    pub pid: u32,
+    /// The port of its `-D` option.
+    pub socks_port: u16,
+    pub command: String,
+}
+
+#[derive(Clone, Debug)]
+struct Process {
+    pid: u32,
+    ppid: u32,
+    argv: Vec<String>,
+}
+
+/// The `ssh` processes started by k8socks runs that are no longer there.
+pub fn find_orphaned_ssh() -> io::Result<Vec<OrphanedSsh>> {
+    Ok(find_orphans(&list_processes()?))
+}
+
+/// Asks process `pid` to terminate, as `kill <pid>` does.
+pub fn terminate(pid: u32) -> io::Result<()> {
+    if !cfg!(unix) {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "only supported on Unix"));
+    }
+    let status = Command::new("kill").arg(pid.to_string()).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("kill {} exited with {}", pid, status)));
+    }
+    Ok(())
+}
+
+/// The SOCKS port of an ssh command line as `build_ssh_args` makes it, or
+/// `None` for any other command. Only commands that carry `SSH_MARKER` count,
+/// so a user's own `ssh -D` tunnel is never taken for an orphan.
+fn k8socks_socks_port(argv: &[String]) -> Option<u16> {
+    let program = argv.first()?;
+    if !program.rsplit('/').next().unwrap_or(program).contains("ssh") {
+        return None;
+    }
+    let has = |arg: &str| argv.iter().any(|a| a == arg);
+    if !(has(crate::SSH_MARKER) && has("-N")) {
+        return None;
+    }
+    let dynamic = argv.iter().position(|a| a == "-D")?;
+    argv.get(dynamic + 1)?.parse().ok()
+}
+
+/// k8socks' ssh processes whose parent has exited, so that they were adopted
+/// by init or a subreaper such as `systemd --user`. An ssh still owned by a
+/// running k8socks, or started from a shell with the command k8socks prints,
+/// has another parent.
+fn find_orphans(processes: &[Process]) -> Vec<OrphanedSsh> {
+    let adopted = |ppid: u32| {
+        ppid <= 1
+            || processes
+                .iter()
+                .find(|parent| parent.pid == ppid)
+                .is_none_or(|parent| parent.argv.first().is_some_and(|a| a.rsplit('/').next() == Some("systemd")))
+    };
+    processes
+        .iter()
+        .filter_map(|process| {
+            let socks_port = k8socks_socks_port(&process.argv)?;
+            adopted(process.ppid).then(|| OrphanedSsh {
+                pid: process.pid,
+                socks_port,
+                command: process.argv.join(" "),
+            })
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn list_processes() -> io::Result<Vec<Process>> {
+    let mut processes = Vec::new();
+    for entry in std::fs::read_dir("/proc")? {
+        let Some(pid) = entry?.file_name().to_str().and_then(|name| name.parse().ok()) else {
+            continue;
+        };
+        // Processes may exit while the table is read; they are skipped.
+        let (Ok(cmdline), Ok(stat)) = (
+            std::fs::read(format!("/proc/{}/cmdline", pid)),
+            std::fs::read_to_string(format!("/proc/{}/stat", pid)),
+        ) else {
+            continue;
+        };
+        let Some(ppid) = parse_stat_ppid(&stat) else {
+            continue;
+        };
+        let argv = cmdline
+            .split(|&b| b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        processes.push(Process { pid, ppid, argv });
+    }
+    Ok(processes)
+}
+
+/// The parent pid in `/proc/<pid>/stat`, which follows the state after the
+/// parenthesized command name; the name itself may contain spaces and `)`.
+#[cfg(target_os = "linux")]
+fn parse_stat_ppid(stat: &str) -> Option<u32> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn list_processes() -> io::Result<Vec<Process>> {
+    let output = Command::new("ps").args(["-A", "-o", "pid=", "-o", "ppid=", "-o", "command="]).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("ps exited with {}", output.status)));
+    }
+    // `ps` joins the arguments with spaces, which is enough for matching.
+    let processes = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let pid = fields.next()?.parse().ok()?;
+            let ppid = fields.next()?.parse().ok()?;
+            let argv = fields.map(str::to_string).collect();
+            Some(Process { pid, ppid, argv })
+        })
+        .collect();
+    Ok(processes)
+}
+
+#[cfg(not(unix))]
+fn list_processes() -> io::Result<Vec<Process>> {
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u32, ppid: u32, command: &str) -> Process {
+        Process {
+            pid,
+            ppid,
+            argv: command.split(' ').map(str::to_string).collect(),
+        }
+    }
+
+    const SSH: &str = "/usr/bin/ssh -o StrictHostKeyChecking=no -o ExitOnForwardFailure=yes -o SetEnv=K8SOCKS=1 \
+                       -N -D 1080 -p 40123 root@127.0.0.1";
+
+    #[test]
+    fn test_k8socks_socks_port() {
+        let argv = |command: &str| command.split(' ').map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(k8socks_socks_port(&argv(SSH)), Some(1080));
+        assert_eq!(k8socks_socks_port(&argv("ssh -N -D 1080 bastion")), None);
+        // A user's tunnel with the same options but without the marker
+        let user_tunnel = SSH.replace(" -o SetEnv=K8SOCKS=1", "");
+        assert_eq!(k8socks_socks_port(&argv(&user_tunnel)), None);
+        assert_eq!(k8socks_socks_port(&argv(&SSH.replace("/usr/bin/ssh", "/usr/bin/curl"))), None);
+        assert_eq!(k8socks_socks_port(&[]), None);
+    }
+
+    #[test]
+    fn test_find_orphans() {
+        let processes = [
+            process(1, 0, "/sbin/init"),
+            process(900, 1, "/usr/lib/systemd/systemd --user"),
+            process(1000, 1, "/usr/local/bin/k8socks deploy"),
+            process(1001, 1000, SSH),
+            // Adopted by init, a subreaper or with its parent not found
+            process(2001, 1, SSH),
+            process(2002, 900, SSH),
+            process(2003, 1999, SSH),
+            // Started from a shell, e.g. to reconnect to a kept pod
+            process(3000, 1, "-bash"),
+            process(3001, 3000, SSH),
+            // A user's own tunnel, also adopted by init
+            process(4001, 1, "ssh -o ExitOnForwardFailure=yes -N -D 1080 bastion"),
+        ];
+        let pids: Vec<u32> = find_orphans(&processes).iter().map(|orphan| orphan.pid).collect();
+        assert_eq!(pids, [2001, 2002, 2003]);
+        assert_eq!(find_orphans(&processes)[0].socks_port, 1080);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_stat_ppid() {
+        assert_eq!(parse_stat_ppid("4242 (ssh) S 1 4242 4242 0 -1"), Some(1));
+        assert_eq!(parse_stat_ppid("4242 (my (odd) name) S 77 4242"), Some(77));
+        assert_eq!(parse_stat_ppid("garbage"), None);
+    }
+}
@@ -0,0 +1,371 @@
+//! A local TCP relay in front of the SOCKS listener of `ssh -D`.
+//!
+//! `ssh` gives no insight into the SOCKS connections it serves, so k8socks
+//! owns the user-facing SOCKS port and relays each connection to the port
+//! `ssh` listens on. That lets it count active connections and stop accepting
+//! new ones while established ones finish. It is also where the HTTP CONNECT
+//! proxy mode plugs in.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+
+use crate::http_connect;
+
+/// The protocol spoken on the user-facing port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyType {
+    /// Connections are passed through to `ssh -D` as they are.
+    Socks5,
+    /// Clients send HTTP CONNECT requests, tunnelled through `ssh -D`.
+    Http,
+}
+
+impl std::str::FromStr for ProxyType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "socks5" => Ok(Self::Socks5),
+            "http" => Ok(Self::Http),
+            other => Err(format!("unknown proxy type '{}'", other)),
+        }
+    }
+}
+
+/// Counts the connections currently relayed.
+#[derive(Clone, Default)]
+pub struct ConnectionTracker {
+    active: Arc<AtomicUsize>,
+    changed: Arc<Notify>,
+}
+
+impl ConnectionTracker {
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    fn track(&self) -> ConnectionGuard {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        self.changed.notify_waiters();
+        ConnectionGuard {
+            tracker: self.clone(),
+        }
+    }
+
+    /// Waits until no connection is active.
+    pub async fn wait_idle(&self) {
+        loop {
+            // Register before checking so a connection closing in between is not missed.
+            let changed = self.changed.notified();
+            if self.active() == 0 {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    /// Waits until no connection has been active for `idle`. A connection
+    /// opening in the meantime starts the wait over once it has closed.
+    pub async fn wait_idle_for(&self, idle: Duration) {
+        loop {
+            let changed = self.changed.notified();
+            if self.active() > 0 {
+                changed.await;
+            } else if tokio::time::timeout(idle, changed).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Marks a connection as active until dropped.
+struct ConnectionGuard {
+    tracker: ConnectionTracker,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.tracker.active.fetch_sub(1, Ordering::SeqCst);
+        self.tracker.changed.notify_waiters();
+    }
+}
+
+pub struct SocksRelay {
+    local_port: u16,
+    upstream_port: u16,
+    tracker: ConnectionTracker,
+    accept_task: JoinHandle<()>,
+}
+
+impl SocksRelay {
+    /// Listens on `127.0.0.1:<local_port>` (`0` picks a free port) and relays
+    /// every connection to the `ssh` SOCKS listener on `upstream_port`,
+    /// speaking `proxy_type` towards the client. With `max_connections`,
+    /// connections beyond that many active ones are closed right away.
+    pub async fn bind(
+        local_port: u16,
+        upstream_port: u16,
+        proxy_type: ProxyType,
+        max_connections: Option<usize>,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", local_port)).await?;
+        Self::from_listener(listener, upstream_port, proxy_type, max_connections)
+    }
+
+    /// Like [`SocksRelay::bind`], on a listener bound beforehand, e.g. to
+    /// claim the port before there is an upstream to relay to.
+    pub fn from_listener(
+        listener: TcpListener,
+        upstream_port: u16,
+        proxy_type: ProxyType,
+        max_connections: Option<usize>,
+    ) -> io::Result<Self> {
+        let local_port = listener.local_addr()?.port();
+        let tracker = ConnectionTracker::default();
+
+        let accept_tracker = tracker.clone();
+        let accept_task = tokio::spawn(async move {
+            // Warn once per run of rejections rather than for each of them.
+            let mut at_limit = false;
+            loop {
+                let (client, addr) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        error!("Failed to accept SOCKS connection: {}", e);
+                        continue;
+                    }
+                };
+                if let Some(max) = max_connections.filter(|&max| accept_tracker.active() >= max) {
+                    if !at_limit {
+                        warn!("Rejecting connections: {} are active, which max_connections allows at most", max);
+                    }
+                    debug!("Rejected connection from {}", addr);
+                    at_limit = true;
+                    continue;
+                }
+                at_limit = false;
+                let guard = accept_tracker.track();
+                tokio::spawn(async move {
+                    let _guard = guard;
+                    debug!("Relaying {:?} connection from {}", proxy_type, addr);
+                    let result = match proxy_type {
+                        ProxyType::Socks5 => relay_connection(client, upstream_port).await,
+                        ProxyType::Http => http_connect::serve_connection(client, upstream_port).await,
+                    };
+                    if let Err(e) = result {
+                        debug!("{:?} connection from {} ended with an error: {}", proxy_type, addr, e);
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            local_port,
+            upstream_port,
+            tracker,
+            accept_task,
+        })
+    }
+
+    /// The port clients connect to.
+    pub fn local_port(&self) -> u16 {
+        self.local_port
+    }
+
+    /// Whether `ssh` is still serving SOCKS: it must accept a connection and
+    /// answer a SOCKS5 greeting within two seconds.
+    pub async fn is_healthy(&self) -> bool {
+        let probe = async {
+            let mut upstream = TcpStream::connect(("127.0.0.1", self.upstream_port)).await?;
+            // Version 5, one method: no authentication.
+            upstream.write_all(&[0x05, 0x01, 0x00]).await?;
+            let mut reply = [0u8; 2];
+            upstream.read_exact(&mut reply).await?;
+            Ok::<_, io::Error>(reply == [0x05, 0x00])
+        };
+        matches!(tokio::time::timeout(Duration::from_secs(2), probe).await, Ok(Ok(true)))
+    }
+
+    pub fn tracker(&self) -> ConnectionTracker {
+        self.tracker.clone()
+    }
+
+    /// Closes the listener. Established connections keep running.
+    pub fn stop_accepting(&self) {
+        self.accept_task.abort();
+    }
+
+    /// Stops accepting new connections and waits up to `timeout` for the
+    /// active ones to finish. Returns whether they all finished in time.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.stop_accepting();
+        tokio::time::timeout(timeout, self.tracker.wait_idle())
+            .await
+            .is_ok()
+    }
+}
+
+impl Drop for SocksRelay {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+async fn relay_connection(mut client: TcpStream, upstream_port: u16) -> io::Result<()> {
+    let mut upstream = TcpStream::connect(("127.0.0.1", upstream_port)).await?;
+    io::copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Starts an upstream that echoes everything back and returns its port.
+    async fn echo_upstream() -> u16 {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = conn.split();
+                    let _ = io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+        port
+    }
+
+    async fn connect_and_ping(port: u16) -> TcpStream {
+        let mut client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        client
+    }
+
+    #[tokio::test]
+    async fn test_relay_tracks_connections() {
+        let relay = SocksRelay::bind(0, echo_upstream().await, ProxyType::Socks5, None).await.unwrap();
+        let tracker = relay.tracker();
+
+        let client = connect_and_ping(relay.local_port()).await;
+        assert_eq!(tracker.active(), 1);
+
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(5), tracker.wait_idle())
+            .await
+            .unwrap();
+        assert_eq!(tracker.active(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_relay_rejects_connections_over_limit() {
+        let relay = SocksRelay::bind(0, echo_upstream().await, ProxyType::Socks5, Some(2)).await.unwrap();
+        let (port, tracker) = (relay.local_port(), relay.tracker());
+        let first = connect_and_ping(port).await;
+        let _second = connect_and_ping(port).await;
+
+        // The third connection is closed without reaching the upstream.
+        let mut third = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let _ = third.write_all(b"ping").await;
+        let mut buf = [0u8; 4];
+        assert!(third.read_exact(&mut buf).await.is_err());
+        assert_eq!(tracker.active(), 2);
+
+        // Once one has finished, there is room again.
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while tracker.active() > 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let _fourth = connect_and_ping(port).await;
+        assert_eq!(tracker.active(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_idle_for_restarts_on_activity() {
+        let tracker = ConnectionTracker::default();
+        let idle = Duration::from_millis(200);
+        let started = tokio::time::Instant::now();
+        let waiter = tokio::spawn({
+            let tracker = tracker.clone();
+            async move { tracker.wait_idle_for(idle).await }
+        });
+
+        // Activity halfway through keeps the proxy alive past the first deadline.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let connection = tracker.track();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!waiter.is_finished());
+
+        // The wait starts over once the connection has closed.
+        drop(connection);
+        waiter.await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_millis(600));
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_active_connections() {
+        let relay = SocksRelay::bind(0, echo_upstream().await, ProxyType::Socks5, None).await.unwrap();
+        let port = relay.local_port();
+        let client = connect_and_ping(port).await;
+
+        let closer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            // New connections are refused while draining.
+            assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+            drop(client);
+        });
+
+        assert!(relay.drain(Duration::from_secs(5)).await);
+        closer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drain_times_out() {
+        let relay = SocksRelay::bind(0, echo_upstream().await, ProxyType::Socks5, None).await.unwrap();
+        let _client = connect_and_ping(relay.local_port()).await;
+
+        assert!(!relay.drain(Duration::from_millis(50)).await);
+        assert_eq!(relay.tracker().active(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_is_healthy_probes_socks_handshake() {
+        // Answers the SOCKS5 greeting like `ssh -D` does.
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let socks_port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut greeting = [0u8; 3];
+                if conn.read_exact(&mut greeting).await.is_ok() {
+                    let _ = conn.write_all(&[0x05, 0x00]).await;
+                }
+            }
+        });
+        let relay = SocksRelay::bind(0, socks_port, ProxyType::Socks5, None).await.unwrap();
+        assert!(relay.is_healthy().await);
+
+        // An upstream that does not speak SOCKS is not healthy.
+        let relay = SocksRelay::bind(0, echo_upstream().await, ProxyType::Socks5, None).await.unwrap();
+        assert!(!relay.is_healthy().await);
+
+        // Nor is one that is gone.
+        let closed = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+        let relay = SocksRelay::bind(0, closed_port, ProxyType::Socks5, None).await.unwrap();
+        assert!(!relay.is_healthy().await);
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::{BTreeMap, HashMap};
+use k8s_openapi::api::core::v1::{Container, HostAlias, PodDNSConfig, TopologySpreadConstraint};
+use super::{Config, PodResources};
+
+/// Builds a `Config` in which only the fields that were set are `Some`, e.g.
+/// a configuration layer to merge over the defaults:
+///
+/// ```
+/// # use k8socks_traits::config::Config;
+/// let config = Config::builder()
+///     .namespace("proxy".to_string())
+///     .local_socks_port(1081)
+///     .build();
+/// assert_eq!(config.local_socks_port, Some(1081));
+/// assert_eq!(config.pod_image, None);
+/// ```
+///
+/// Every setter takes the value or an `Option` of it, so optional values such
+/// as CLI flags can be passed through as they are.
+#[derive(Debug, Clone)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
+
+/// Generates the setters and the all-`None` starting point from the list of
+/// fields. Listing every field keeps the struct literal exhaustive, so a new
+/// `Config` field that is missing here fails to compile.
+macro_rules! config_builder {
+    ($($field:ident: $ty:ty,)*) => {
+        impl Config {
+            /// Starts a `Config` with every field unset. Unlike `Config::default()`,
+            /// this carries no default values.
+            pub fn builder() -> ConfigBuilder {
+                ConfigBuilder {
+                    config: Config { $($field: None,)* },
+                }
+            }
+        }
+
+        impl ConfigBuilder {
+            $(
+                pub fn $field(mut self, value: impl Into<Option<$ty>>) -> Self {
+                    self.config.$field = value.into();
+                    self
+                }
+            )*
+        }
+    };
+}
+
+config_builder! {
+    kubeconfig: String,
+    context: String,
+    namespace: String,
+    ssh_public_key_path: String,
+    ssh_username: String,
+    local_socks_port: u16,
+    pod_ttl_seconds: u64,
+    pod_image: String,
+    pod_resources: PodResources,
+    pod_labels: HashMap<String, String>,
+    pod_annotations: HashMap<String, String>,
+    log_level: String,
+    ssh_binary_path: String,
+    ssh_connect_timeout_seconds: u64,
+    pod_restart_policy: String,
+    ssh_predial_attempts: u32,
+    ssh_predial_interval_millis: u64,
+    pod_env: HashMap<String, String>,
+    pod_image_kind: String,
+    pod_ssh_port: u16,
+    pod_uid: u32,
+    pod_gid: u32,
+    ssh_verbosity: u8,
+    drain_timeout_seconds: u64,
+    pod_ready_timeout_seconds: u64,
+    image_pull_timeout_seconds: u64,
+    pod_dns_policy: String,
+    pod_dns_config: PodDNSConfig,
+    pod_selector_label: String,
+    pod_max_lifetime_seconds: u64,
+    ssh_jump_host: String,
+    pod_termination_grace_period_seconds: i64,
+    ssh_public_key_paths: Vec<String>,
+    pod_poll_interval_millis: u64,
+    proxy_type: String,
+    pod_automount_service_account_token: bool,
+    pod_host_aliases: Vec<HostAlias>,
+    pod_host_network: bool,
+    session_timeout_seconds: u64,
+    forwarded_local_port: u16,
+    idle_timeout_seconds: u64,
+    annotate_identity: bool,
+    contexts: Vec<String>,
+    log_connections: bool,
+    health_port: u16,
+    delete_pod_on_exit: bool,
+    ssh_forward_agent: bool,
+    pod_topology_spread_constraints: Vec<TopologySpreadConstraint>,
+    ssh_target_host: String,
+    cleanup_jitter_millis: u64,
+    ssh_use_agent_key: bool,
+    ssh_agent_key: String,
+    pod_hostname: String,
+    pod_subdomain: String,
+    kube_qps: f64,
+    kube_burst: u32,
+    log_throughput: bool,
+    log_throughput_interval_seconds: u64,
+    pod_init_containers: Vec<Container>,
+    pod_host_keys_init: bool,
+    pod_expiry_annotation_key: String,
+    pod_expiry_annotation_format: String,
+    ssh_ciphers: String,
+    ssh_kex_algorithms: String,
+    kube_ca_cert_path: String,
+    kube_insecure_skip_tls_verify: bool,
+    profiles: BTreeMap<String, Config>,
+    pod_spec_patch: serde_json::Value,
+    sshd_config: String,
+    max_connections: usize,
+    production_context_pattern: String,
+    ssh_config_file: String,
+    rate_limit: u64,
+    pod_owner_sentinel: bool,
+}
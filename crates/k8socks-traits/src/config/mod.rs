@@ -1,9 +1,16 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use k8s_openapi::api::core::v1::{Container, HostAlias, PodDNSConfig, TopologySpreadConstraint};
+use k8socks_util::template;
 use merge::Merge;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+mod builder;
+
+pub use builder::ConfigBuilder;
+
 /// A custom merge strategy for `Option<T>` fields. It overwrites the destination
 /// (`left`) with the source (`right`) only if the source is `Some`.
 fn overwrite_if_some<T>(left: &mut Option<T>, right: Option<T>) {
@@ -12,6 +19,28 @@ fn overwrite_if_some<T>(left: &mut Option<T>, right: Option<T>) {
     }
 }
 
+/// A merge strategy for upper limits: when both sides are `Some`, the smaller
+/// value wins, so a later layer (e.g. CLI flags) can tighten a limit set by an
+/// earlier one (e.g. an admin-provided config file) but never loosen it.
+fn keep_min<T: Ord>(left: &mut Option<T>, right: Option<T>) {
+    *left = match (left.take(), right) {
+        (Some(l), Some(r)) => Some(l.min(r)),
+        (l, r) => r.or(l),
+    };
+}
+
+/// A merge strategy for optional structs: when both sides are `Some`, the
+/// struct's own fields are merged, so that a layer which sets only some of
+/// them keeps the others of an earlier layer.
+fn merge_inner<T: Merge>(left: &mut Option<T>, right: Option<T>) {
+    if let Some(right) = right {
+        match left {
+            Some(left) => left.merge(right),
+            None => *left = Some(right),
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("Configuration file not found at any of the expected locations")]
@@ -20,9 +49,13 @@ pub enum ConfigError {
     Io(#[from] std::io::Error),
     #[error("Failed to parse configuration file: {0}")]
     Parse(#[from] serde_json::Error),
+    #[error("Failed to parse TOML configuration file: {0}")]
+    ParseToml(#[from] toml::de::Error),
+    #[error("Invalid configuration: {0}")]
+    Invalid(String),
 }
 
-#[derive(Deserialize, Merge, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Merge, Debug, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct PodResources {
     #[merge(strategy = overwrite_if_some)]
@@ -33,7 +66,35 @@ pub struct PodResources {
     pub memory: Option<String>,
 }
 
-#[derive(Deserialize, Merge, Debug, Clone, PartialEq)]
+/// Parses `cpu=<quantity>,memory=<quantity>` as `--resources` takes it;
+/// either one may be left out, e.g. `memory=256Mi`.
+impl FromStr for PodResources {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut resources = PodResources { cpu: None, memory: None };
+        for pair in s.split(',').map(str::trim) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected cpu=<quantity> or memory=<quantity>, got '{}'", pair))?;
+            let (key, value) = (key.trim(), value.trim());
+            let slot = match key {
+                "cpu" => &mut resources.cpu,
+                "memory" => &mut resources.memory,
+                _ => return Err(format!("unknown resource '{}', expected cpu or memory", key)),
+            };
+            if value.is_empty() {
+                return Err(format!("{} needs a quantity, e.g. {}", key, if key == "cpu" { "250m" } else { "256Mi" }));
+            }
+            if slot.replace(value.to_string()).is_some() {
+                return Err(format!("{} is given more than once", key));
+            }
+        }
+        Ok(resources)
+    }
+}
+
+#[derive(Deserialize, Serialize, Merge, Debug, Clone, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     #[merge(strategy = overwrite_if_some)]
@@ -60,7 +121,7 @@ pub struct Config {
     #[merge(strategy = overwrite_if_some)]
     #[serde(default)]
     pub pod_image: Option<String>,
-    #[merge(strategy = overwrite_if_some)]
+    #[merge(strategy = merge_inner)]
     #[serde(default)]
     pub pod_resources: Option<PodResources>,
     #[merge(strategy = overwrite_if_some)]
@@ -72,14 +133,227 @@ pub struct Config {
     #[merge(strategy = overwrite_if_some)]
     #[serde(default)]
     pub log_level: Option<String>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_binary_path: Option<String>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_connect_timeout_seconds: Option<u64>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_restart_policy: Option<String>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_predial_attempts: Option<u32>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_predial_interval_millis: Option<u64>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_env: Option<HashMap<String, String>>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_image_kind: Option<String>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_ssh_port: Option<u16>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_uid: Option<u32>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_gid: Option<u32>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_verbosity: Option<u8>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub drain_timeout_seconds: Option<u64>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_ready_timeout_seconds: Option<u64>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub image_pull_timeout_seconds: Option<u64>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_dns_policy: Option<String>,
+    /// Passed through as the pod's `dnsConfig`, using the Kubernetes field names.
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_dns_config: Option<PodDNSConfig>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_selector_label: Option<String>,
+    #[merge(strategy = keep_min)]
+    #[serde(default)]
+    pub pod_max_lifetime_seconds: Option<u64>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_jump_host: Option<String>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_termination_grace_period_seconds: Option<i64>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_public_key_paths: Option<Vec<String>>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_poll_interval_millis: Option<u64>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub proxy_type: Option<String>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_automount_service_account_token: Option<bool>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_host_aliases: Option<Vec<HostAlias>>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_host_network: Option<bool>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub session_timeout_seconds: Option<u64>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub forwarded_local_port: Option<u16>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub idle_timeout_seconds: Option<u64>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub annotate_identity: Option<bool>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub contexts: Option<Vec<String>>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub log_connections: Option<bool>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub health_port: Option<u16>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub delete_pod_on_exit: Option<bool>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_forward_agent: Option<bool>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_topology_spread_constraints: Option<Vec<TopologySpreadConstraint>>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_target_host: Option<String>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub cleanup_jitter_millis: Option<u64>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_use_agent_key: Option<bool>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_agent_key: Option<String>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_hostname: Option<String>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_subdomain: Option<String>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub kube_qps: Option<f64>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub kube_burst: Option<u32>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub log_throughput: Option<bool>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub log_throughput_interval_seconds: Option<u64>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_init_containers: Option<Vec<Container>>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_host_keys_init: Option<bool>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_expiry_annotation_key: Option<String>,
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_expiry_annotation_format: Option<String>,
+    /// Passed to `ssh` as `-o Ciphers=...`, e.g. `aes256-gcm@openssh.com,aes256-ctr`.
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_ciphers: Option<String>,
+    /// Passed to `ssh` as `-o KexAlgorithms=...`.
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_kex_algorithms: Option<String>,
+    /// A PEM file of CA certificates to trust for the API server, on top of
+    /// those in the kubeconfig.
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub kube_ca_cert_path: Option<String>,
+    /// Do not verify the API server's certificate at all. For throwaway dev
+    /// clusters only.
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub kube_insecure_skip_tls_verify: Option<bool>,
+    /// Named sets of settings in the config file, e.g. one per cluster, of
+    /// which `--profile` merges one over the rest of the file.
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub profiles: Option<BTreeMap<String, Config>>,
+    /// A JSON merge patch (RFC 7386) applied to the pod's spec before it is
+    /// created, for settings k8socks has no option for.
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_spec_patch: Option<serde_json::Value>,
+    /// Extra sshd_config lines for the `sshd` image, e.g. `MaxSessions 4`,
+    /// one per line. Each becomes an `-o` option of sshd.
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub sshd_config: Option<String>,
+    /// How many proxy connections may be open at once; further ones are
+    /// closed right away. Unlimited when unset.
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    /// A regular expression for kubeconfig context names that look like
+    /// production, e.g. `prod`. Deploying to a matching context asks for
+    /// confirmation on a terminal. No context is gated when unset.
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub production_context_pattern: Option<String>,
+    /// An ssh config file for the `ssh` client, passed as `-F`. When unset,
+    /// `-F none` makes ssh ignore `~/.ssh/config`, unless `ssh_jump_host` is
+    /// set.
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub ssh_config_file: Option<String>,
+    /// Caps the data through the tunnel, both directions together, at this
+    /// many bytes per second. Unlimited when unset.
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub rate_limit: Option<u64>,
+    /// Creates a ConfigMap that owns the pod, so that the pod is garbage
+    /// collected once the ConfigMap is deleted, e.g. by a reaper after the
+    /// `pod_expiry_annotation_key` it carries.
+    #[merge(strategy = overwrite_if_some)]
+    #[serde(default)]
+    pub pod_owner_sentinel: Option<bool>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            kubeconfig: Some("~/.kube/config".to_string()),
+            kubeconfig: None,
             context: None,
-            namespace: Some("default".to_string()),
+            // Resolved from the kubeconfig context or in-cluster service account.
+            namespace: None,
             ssh_public_key_path: Some("~/.ssh/id_rsa.pub".to_string()),
             ssh_username: Some("k8socks".to_string()),
             local_socks_port: Some(1080),
@@ -92,11 +366,480 @@ impl Default for Config {
             pod_labels: Some([("app".to_string(), "k8socks".to_string())].into()),
             pod_annotations: Some(HashMap::new()),
             log_level: Some("info".to_string()),
+            ssh_binary_path: Some("ssh".to_string()),
+            ssh_connect_timeout_seconds: Some(10),
+            // The container exits once the TTL elapses; `Never` lets the pod
+            // finish instead of restarting sshd for another TTL period.
+            pod_restart_policy: Some("Never".to_string()),
+            ssh_predial_attempts: Some(10),
+            ssh_predial_interval_millis: Some(200),
+            pod_env: None,
+            pod_image_kind: Some("linuxserver".to_string()),
+            pod_ssh_port: None,
+            pod_uid: Some(1000),
+            pod_gid: Some(1000),
+            ssh_verbosity: Some(0),
+            drain_timeout_seconds: Some(0),
+            pod_ready_timeout_seconds: Some(60),
+            image_pull_timeout_seconds: Some(300),
+            pod_dns_policy: None,
+            pod_dns_config: None,
+            pod_selector_label: None,
+            pod_max_lifetime_seconds: None,
+            ssh_jump_host: None,
+            pod_termination_grace_period_seconds: Some(5),
+            ssh_public_key_paths: None,
+            pod_poll_interval_millis: Some(500),
+            proxy_type: Some("socks5".to_string()),
+            pod_automount_service_account_token: Some(false),
+            pod_host_aliases: None,
+            pod_host_network: None,
+            session_timeout_seconds: None,
+            forwarded_local_port: None,
+            idle_timeout_seconds: None,
+            annotate_identity: Some(false),
+            contexts: None,
+            log_connections: Some(false),
+            health_port: None,
+            delete_pod_on_exit: Some(true),
+            ssh_forward_agent: Some(false),
+            pod_topology_spread_constraints: None,
+            ssh_target_host: Some("127.0.0.1".to_string()),
+            cleanup_jitter_millis: Some(500),
+            ssh_use_agent_key: Some(false),
+            ssh_agent_key: None,
+            pod_hostname: None,
+            pod_subdomain: None,
+            kube_qps: None,
+            kube_burst: None,
+            log_throughput: Some(false),
+            log_throughput_interval_seconds: Some(5),
+            pod_init_containers: None,
+            pod_host_keys_init: Some(false),
+            pod_expiry_annotation_key: None,
+            pod_expiry_annotation_format: Some("rfc3339".to_string()),
+            ssh_ciphers: None,
+            ssh_kex_algorithms: None,
+            kube_ca_cert_path: None,
+            kube_insecure_skip_tls_verify: Some(false),
+            profiles: None,
+            pod_spec_patch: None,
+            sshd_config: None,
+            max_connections: None,
+            production_context_pattern: None,
+            ssh_config_file: None,
+            rate_limit: None,
+            pod_owner_sentinel: None,
+        }
+    }
+}
+
+impl Config {
+    /// Checks the merged configuration for values the cluster would reject.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(profiles) = &self.profiles {
+            if let Some(name) = profiles.keys().find(|name| name.trim().is_empty()) {
+                return Err(ConfigError::Invalid(format!("profile name '{}' must not be empty", name)));
+            }
+            if let Some((name, _)) = profiles.iter().find(|(_, profile)| profile.profiles.is_some()) {
+                return Err(ConfigError::Invalid(format!("profile '{}' must not define profiles of its own", name)));
+            }
+        }
+        if let Some(policy) = &self.pod_restart_policy {
+            if !["Always", "OnFailure", "Never"].contains(&policy.as_str()) {
+                return Err(ConfigError::Invalid(format!(
+                    "pod_restart_policy must be one of Always, OnFailure or Never, got '{}'",
+                    policy
+                )));
+            }
+        }
+        if let Some(kind) = &self.pod_image_kind {
+            if !["linuxserver", "sshd"].contains(&kind.as_str()) {
+                return Err(ConfigError::Invalid(format!(
+                    "pod_image_kind must be one of linuxserver or sshd, got '{}'",
+                    kind
+                )));
+            }
+        }
+        if let Some(sshd_config) = &self.sshd_config {
+            if self.pod_image_kind.as_deref() != Some("sshd") {
+                return Err(ConfigError::Invalid(
+                    "sshd_config needs pod_image_kind sshd; the linuxserver image starts sshd itself".to_string(),
+                ));
+            }
+            for line in sshd_config.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                check_sshd_config_line(line)
+                    .map_err(|why| ConfigError::Invalid(format!("sshd_config line '{}' {}", line, why)))?;
+            }
+        }
+        if self.pod_spec_patch.as_ref().is_some_and(|patch| !patch.is_object()) {
+            return Err(ConfigError::Invalid("pod_spec_patch must be a JSON object".to_string()));
+        }
+        if self.pod_host_keys_init == Some(true) && self.pod_image_kind.as_deref() != Some("sshd") {
+            return Err(ConfigError::Invalid(
+                "pod_host_keys_init needs pod_image_kind sshd; the linuxserver image sets up its own keys".to_string(),
+            ));
+        }
+        if let Some(format) = &self.pod_expiry_annotation_format {
+            if !["rfc3339", "duration"].contains(&format.as_str()) {
+                return Err(ConfigError::Invalid(format!(
+                    "pod_expiry_annotation_format must be one of rfc3339 or duration, got '{}'",
+                    format
+                )));
+            }
+        }
+        if let Some(proxy_type) = &self.proxy_type {
+            if !["socks5", "http"].contains(&proxy_type.as_str()) {
+                return Err(ConfigError::Invalid(format!(
+                    "proxy_type must be one of socks5 or http, got '{}'",
+                    proxy_type
+                )));
+            }
+        }
+        if let Some(jump) = &self.ssh_jump_host {
+            if !jump.split(',').all(is_valid_jump_hop) {
+                return Err(ConfigError::Invalid(format!(
+                    "ssh_jump_host must be a comma-separated list of [user@]host[:port], got '{}'",
+                    jump
+                )));
+            }
+        }
+        for (key, algorithms) in [("ssh_ciphers", &self.ssh_ciphers), ("ssh_kex_algorithms", &self.ssh_kex_algorithms)] {
+            if let Some(algorithms) = algorithms {
+                if !is_valid_algorithm_list(algorithms) {
+                    return Err(ConfigError::Invalid(format!(
+                        "{} must be a comma-separated list of algorithm names, got '{}'",
+                        key, algorithms
+                    )));
+                }
+            }
+        }
+        if let Some(host) = &self.ssh_target_host {
+            if host.is_empty() || host.contains(|c: char| c.is_whitespace() || c == '@') {
+                return Err(ConfigError::Invalid(format!(
+                    "ssh_target_host must be a host name or address, got '{}'",
+                    host
+                )));
+            }
         }
+        self.validate_k8s_names()?;
+        if let Some(qps) = self.kube_qps {
+            if !(qps.is_finite() && qps > 0.0) {
+                return Err(ConfigError::Invalid(format!("kube_qps must be a positive number, got {}", qps)));
+            }
+        }
+        if self.kube_burst == Some(0) {
+            return Err(ConfigError::Invalid("kube_burst must be at least 1".to_string()));
+        }
+        if self.max_connections == Some(0) {
+            return Err(ConfigError::Invalid("max_connections must be at least 1".to_string()));
+        }
+        if self.rate_limit == Some(0) {
+            return Err(ConfigError::Invalid("rate_limit must be at least 1 byte per second".to_string()));
+        }
+        if let Some(pattern) = &self.production_context_pattern {
+            if let Err(e) = regex::Regex::new(pattern) {
+                return Err(ConfigError::Invalid(format!(
+                    "production_context_pattern must be a regular expression: {}",
+                    e
+                )));
+            }
+        }
+        if self.log_throughput_interval_seconds == Some(0) {
+            return Err(ConfigError::Invalid(
+                "log_throughput_interval_seconds must be at least 1".to_string(),
+            ));
+        }
+        if let Some(contexts) = &self.contexts {
+            if contexts.iter().any(|c| c.trim().is_empty()) {
+                return Err(ConfigError::Invalid("contexts must not contain empty names".to_string()));
+            }
+            let last_offset = contexts.len().saturating_sub(1);
+            for (key, port) in [
+                ("local_socks_port", self.local_socks_port),
+                ("forwarded_local_port", self.forwarded_local_port),
+                ("health_port", self.health_port),
+            ] {
+                if let Some(port) = port.filter(|&p| p != 0) {
+                    if usize::from(port) + last_offset > usize::from(u16::MAX) {
+                        return Err(ConfigError::Invalid(format!(
+                            "{} {} leaves no room for one port per context ({} contexts)",
+                            key,
+                            port,
+                            contexts.len()
+                        )));
+                    }
+                }
+            }
+        }
+        self.validate_ports()?;
+        Ok(())
+    }
+
+    /// Checks that the ports k8socks listens on locally do not overlap, which
+    /// would otherwise only show up as an "address in use" halfway through
+    /// startup. With `contexts`, each port stands for the range of ports
+    /// `per_context` hands out; a port of `0` picks a free port and never
+    /// conflicts.
+    fn validate_ports(&self) -> Result<(), ConfigError> {
+        let count = self.contexts.as_ref().map_or(1, |contexts| contexts.len().max(1));
+        let ranges: Vec<_> = [
+            ("local_socks_port", Some(self.local_socks_port.unwrap_or(1080))),
+            ("forwarded_local_port", self.forwarded_local_port),
+            ("health_port", self.health_port),
+        ]
+        .into_iter()
+        .filter_map(|(key, port)| {
+            let first = usize::from(port.filter(|&p| p != 0)?);
+            Some((key, first, first + count - 1))
+        })
+        .collect();
+        let describe = |first: usize, last: usize| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            }
+        };
+        for (i, &(key, first, last)) in ranges.iter().enumerate() {
+            for &(other, other_first, other_last) in &ranges[i + 1..] {
+                if first <= other_last && other_first <= last {
+                    return Err(ConfigError::Invalid(format!(
+                        "{} {} and {} {} overlap; each needs ports of its own",
+                        key,
+                        describe(first, last),
+                        other,
+                        describe(other_first, other_last)
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the namespace, pod labels and annotation keys against the
+    /// Kubernetes naming rules, which the API server would otherwise enforce
+    /// with an opaque 422 when the pod is created.
+    fn validate_k8s_names(&self) -> Result<(), ConfigError> {
+        if let Some(namespace) = &self.namespace {
+            check_dns_label(namespace)
+                .map_err(|why| ConfigError::Invalid(format!("namespace '{}' {}", namespace, why)))?;
+        }
+        for (key, name) in [("pod_hostname", &self.pod_hostname), ("pod_subdomain", &self.pod_subdomain)] {
+            if let Some(name) = name {
+                check_dns_label(name).map_err(|why| ConfigError::Invalid(format!("{} '{}' {}", key, name, why)))?;
+            }
+        }
+        if let Some(labels) = &self.pod_labels {
+            let mut labels: Vec<_> = labels.iter().collect();
+            labels.sort();
+            for (key, value) in &labels {
+                check_qualified_name(key)
+                    .map_err(|why| ConfigError::Invalid(format!("pod_labels key '{}' {}", key, why)))?;
+                // Templated values are sanitized once rendered, so only their
+                // placeholders are checked here.
+                if template::placeholders(value).is_empty() {
+                    check_label_value(value).map_err(|why| {
+                        ConfigError::Invalid(format!("pod_labels value '{}' of '{}' {}", value, key, why))
+                    })?;
+                } else {
+                    check_placeholders(value).map_err(|why| {
+                        ConfigError::Invalid(format!("pod_labels value '{}' of '{}' {}", value, key, why))
+                    })?;
+                }
+            }
+            // Templated labels differ between pods, so they are left out of
+            // the selector that finds pods again; something has to remain.
+            let all_templated = labels.iter().all(|(_, value)| !template::placeholders(value).is_empty());
+            if !labels.is_empty() && all_templated && self.pod_selector_label.is_none() {
+                return Err(ConfigError::Invalid(
+                    "pod_labels needs at least one label without placeholders, or a pod_selector_label, to find its pods again"
+                        .to_string(),
+                ));
+            }
+        }
+        if let Some(annotations) = &self.pod_annotations {
+            let mut annotations: Vec<_> = annotations.iter().collect();
+            annotations.sort();
+            for (key, value) in annotations {
+                check_qualified_name(key)
+                    .map_err(|why| ConfigError::Invalid(format!("pod_annotations key '{}' {}", key, why)))?;
+                check_placeholders(value).map_err(|why| {
+                    ConfigError::Invalid(format!("pod_annotations value '{}' of '{}' {}", value, key, why))
+                })?;
+            }
+        }
+        if let Some(key) = &self.pod_expiry_annotation_key {
+            check_qualified_name(key)
+                .map_err(|why| ConfigError::Invalid(format!("pod_expiry_annotation_key '{}' {}", key, why)))?;
+        }
+        Ok(())
+    }
+
+    /// Merges the settings of profile `name` over this configuration.
+    pub fn apply_profile(&mut self, name: &str) -> Result<(), ConfigError> {
+        let profiles = self.profiles.clone().unwrap_or_default();
+        let Some(profile) = profiles.get(name) else {
+            let known: Vec<&str> = profiles.keys().map(String::as_str).collect();
+            return Err(ConfigError::Invalid(match known.as_slice() {
+                [] => format!("profile '{}' not found; the config file defines no profiles", name),
+                _ => format!("profile '{}' not found; the config file defines {}", name, known.join(", ")),
+            }));
+        };
+        self.merge(profile.clone());
+        Ok(())
+    }
+
+    /// Splits the configuration into one per entry of `contexts`, for
+    /// deploying to several clusters at once. The n-th context (0-based) gets
+    /// `local_socks_port + n`, and `forwarded_local_port + n` and
+    /// `health_port + n` when those are set; a port of `0` stays `0` so that
+    /// each picks a free port. Without
+    /// `contexts`, the configuration is returned as it is.
+    pub fn per_context(&self) -> Vec<Config> {
+        let Some(contexts) = self.contexts.as_ref().filter(|c| !c.is_empty()) else {
+            return vec![self.clone()];
+        };
+        // `validate` makes sure the offsets fit.
+        let offset = |port: u16, n: usize| if port == 0 { 0 } else { port + n as u16 };
+        contexts
+            .iter()
+            .enumerate()
+            .map(|(n, context)| Config {
+                context: Some(context.clone()),
+                contexts: None,
+                local_socks_port: Some(offset(self.local_socks_port.unwrap_or(1080), n)),
+                forwarded_local_port: self.forwarded_local_port.map(|port| offset(port, n)),
+                health_port: self.health_port.map(|port| offset(port, n)),
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+/// Checks an RFC 1123 label, as used for namespace names: at most 63
+/// lowercase alphanumeric characters or `-`, starting and ending with an
+/// alphanumeric character.
+fn check_dns_label(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > 63 {
+        return Err("must be 1 to 63 characters long".to_string());
+    }
+    if !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+        return Err("may only contain lowercase letters, digits and '-'".to_string());
     }
+    if name.starts_with('-') || name.ends_with('-') {
+        return Err("must start and end with a letter or digit".to_string());
+    }
+    Ok(())
+}
+
+/// Checks a label or annotation key: an optional DNS subdomain prefix and
+/// `/`, followed by a name of at most 63 characters.
+fn check_qualified_name(key: &str) -> Result<(), String> {
+    let name = match key.split_once('/') {
+        Some((prefix, name)) => {
+            let valid_prefix = prefix.len() <= 253 && prefix.split('.').all(|part| check_dns_label(part).is_ok());
+            if !valid_prefix {
+                return Err(format!(
+                    "has an invalid prefix '{}': it must be a lowercase DNS subdomain such as 'example.com'",
+                    prefix
+                ));
+            }
+            name
+        }
+        None => key,
+    };
+    if name.is_empty() || name.len() > 63 {
+        return Err("must have a name part of 1 to 63 characters".to_string());
+    }
+    check_name_chars(name)
+}
+
+/// Checks that `value` only uses the placeholders k8socks fills in.
+fn check_placeholders(value: &str) -> Result<(), String> {
+    template::render(value, |name| template::PLACEHOLDERS.contains(&name).then(String::new)).map(drop)
+}
+
+/// Checks a label value: empty, or at most 63 characters like a key's name part.
+fn check_label_value(value: &str) -> Result<(), String> {
+    if value.len() > 63 {
+        return Err("must be at most 63 characters long".to_string());
+    }
+    if value.is_empty() {
+        return Ok(());
+    }
+    check_name_chars(value)
+}
+
+fn check_name_chars(name: &str) -> Result<(), String> {
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+        return Err("may only contain letters, digits, '-', '_' and '.'".to_string());
+    }
+    let alphanumeric_ends = name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name.ends_with(|c: char| c.is_ascii_alphanumeric());
+    if !alphanumeric_ends {
+        return Err("must start and end with a letter or digit".to_string());
+    }
+    Ok(())
+}
+
+/// Checks a `Keyword value` line of `sshd_config`, which is passed to sshd
+/// as `-o`. `Match` blocks cannot be given that way.
+fn check_sshd_config_line(line: &str) -> Result<(), String> {
+    if line.chars().any(char::is_control) {
+        return Err("must not contain control characters".to_string());
+    }
+    let (keyword, value) = line
+        .split_once(|c: char| c.is_whitespace() || c == '=')
+        .ok_or_else(|| "must be a keyword followed by a value".to_string())?;
+    if keyword.is_empty() || !keyword.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err("must start with an sshd keyword such as MaxSessions".to_string());
+    }
+    if value.trim_start_matches(|c: char| c.is_whitespace() || c == '=').is_empty() {
+        return Err("must be a keyword followed by a value".to_string());
+    }
+    if keyword.eq_ignore_ascii_case("match") {
+        return Err("cannot open a Match block; sshd only takes single options on its command line".to_string());
+    }
+    Ok(())
+}
+
+/// Checks one `[user@]host[:port]` hop of an ssh `-J` spec.
+fn is_valid_jump_hop(hop: &str) -> bool {
+    let host_port = match hop.split_once('@') {
+        Some((user, rest)) if !user.is_empty() => rest,
+        Some(_) => return false,
+        None => hop,
+    };
+    let host = match host_port.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        Some(_) => return false,
+        None => host_port,
+    };
+    !host.is_empty()
+        && !host.starts_with('-')
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+/// Checks an ssh algorithm list such as `aes256-ctr,aes128-ctr`, optionally
+/// prefixed with `+`, `-` or `^` to change ssh's defaults instead.
+fn is_valid_algorithm_list(algorithms: &str) -> bool {
+    let list = algorithms.strip_prefix(['+', '-', '^']).unwrap_or(algorithms);
+    list.split(',').all(|name| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '@' | '_' | '*'))
+    })
 }
 
 pub trait ConfigService {
     fn load_from_paths() -> Result<Config, ConfigError>;
+    fn load_from_file(path: &Path) -> Result<Config, ConfigError>;
     fn expand_tilde<P: AsRef<Path>>(path: P) -> Option<PathBuf>;
 }
\ No newline at end of file
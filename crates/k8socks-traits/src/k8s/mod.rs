@@ -1,9 +1,14 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use async_trait::async_trait;
 use kube::config::{InferConfigError, KubeconfigError};
 use kube::Error as KubeError;
 use thiserror::Error;
 use tokio::task::JoinHandle;
-use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::api::core::v1::{Event, Pod};
+use k8s_openapi::chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
 use crate::config::Config;
 
 #[derive(Error, Debug)]
@@ -14,34 +19,254 @@ pub enum K8sError {
     KubeConfig(#[from] KubeconfigError),
     #[error("Failed to infer Kubernetes config: {0}")]
     InferConfig(#[from] InferConfigError),
+    #[error("Failed to read the CA certificates in '{0}': {1}")]
+    CaCert(String, String),
+    #[error("pod_spec_patch does not apply to the pod: {0}")]
+    InvalidPodSpecPatch(String),
     #[error("Pod was not ready in time")]
     PodNotReady,
+    #[error("Pod '{0}' was still there when the wait for its deletion timed out")]
+    PodNotDeleted(String),
     #[error("Failed to read SSH public key at '{0}': {1}")]
     SshKeyError(String, std::io::Error),
+    #[error("Invalid SSH public key in '{0}': {1}")]
+    InvalidSshKey(String, String),
     #[error("Pod was not found: {0}")]
     PodNotFound(String),
     #[error("Port forwarding failed: {0}")]
     PortForwardFailed(#[from] std::io::Error),
+    #[error("sshd in the pod did not answer on forwarded port {0}")]
+    SshdUnreachable(u16),
+    #[error(
+        "Not allowed to {verb} {resource} in namespace '{namespace}'. Ask a cluster admin for \
+         the RBAC permission; check it with `kubectl auth can-i {verb} {resource} -n {namespace}`"
+    )]
+    Forbidden {
+        verb: String,
+        resource: String,
+        namespace: String,
+    },
+    #[error(
+        "Namespace '{0}' does not exist. Create it with `kubectl create namespace {0}` \
+         or choose another one with --namespace"
+    )]
+    NamespaceNotFound(String),
+    #[error("No namespace configured. Set `namespace` in the config file or pass --namespace")]
+    NoNamespace,
+    #[error("Cannot talk to the Kubernetes API: {detail}")]
+    Connection { detail: String },
+    #[error(
+        "The pod does not fit the resource quotas of its namespace. Lower pod_resources, \
+         free up quota or leave out --strict-quota to try anyway"
+    )]
+    QuotaExceeded,
 }
 
+/// A resource the pod would request more of than a `ResourceQuota` has left.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuotaProblem {
+    pub quota: String,
+    pub resource: String,
+    pub hard: String,
+    pub used: String,
+    pub requested: String,
+}
+
+impl std::fmt::Display for QuotaProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ResourceQuota '{}' allows {} {} with {} already used, but the pod requests {}",
+            self.quota, self.hard, self.resource, self.used, self.requested
+        )
+    }
+}
+
+/// A k8socks pod, plus the details of it that were known when the reference
+/// was made. The details are informational; `name` and `namespace` identify
+/// the pod.
 #[derive(Clone, Debug)]
 pub struct PodRef {
     pub name: String,
     pub namespace: String,
+    pub creation_timestamp: Option<DateTime<Utc>>,
+    pub phase: Option<String>,
+    pub node_name: Option<String>,
+}
+
+impl PodRef {
+    pub fn new(name: impl Into<String>, namespace: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            namespace: namespace.into(),
+            creation_timestamp: None,
+            phase: None,
+            node_name: None,
+        }
+    }
+
+    /// Time since the pod was created, if known.
+    pub fn age(&self) -> Option<std::time::Duration> {
+        self.creation_timestamp
+            .and_then(|created| (Utc::now() - created).to_std().ok())
+    }
+}
+
+impl From<&Pod> for PodRef {
+    fn from(pod: &Pod) -> Self {
+        let status = pod.status.as_ref();
+        Self {
+            name: pod.metadata.name.clone().unwrap_or_default(),
+            namespace: pod.metadata.namespace.clone().unwrap_or_default(),
+            creation_timestamp: pod.metadata.creation_timestamp.as_ref().map(|t| t.0),
+            phase: status.and_then(|s| s.phase.clone()),
+            node_name: pod.spec.as_ref().and_then(|s| s.node_name.clone()),
+        }
+    }
+}
+
+/// A k8socks pod as printed by `status` in the structured output formats.
+/// `created` is an ISO-8601 (RFC 3339) timestamp in UTC.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PodSummary {
+    pub namespace: String,
+    pub name: String,
+    pub phase: Option<String>,
+    pub node: Option<String>,
+    pub created: Option<String>,
+    pub age_seconds: Option<u64>,
+}
+
+impl From<&PodRef> for PodSummary {
+    fn from(pod: &PodRef) -> Self {
+        Self {
+            namespace: pod.namespace.clone(),
+            name: pod.name.clone(),
+            phase: pod.phase.clone(),
+            node: pod.node_name.clone(),
+            created: pod
+                .creation_timestamp
+                .map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            age_seconds: pod.age().map(|age| age.as_secs()),
+        }
+    }
+}
+
+/// Bytes moved through one connection of a port-forward.
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+/// Bytes moved through a port-forward, summed over all of its connections,
+/// and per connection for those still open.
+#[derive(Debug, Default)]
+pub struct TransferStats {
+    sent: AtomicU64,
+    received: AtomicU64,
+    next_connection: AtomicU64,
+    connections: Mutex<BTreeMap<u64, Arc<ConnectionStats>>>,
+}
+
+/// The byte counts of a port-forward at one point in time. `connections`
+/// maps the id of each open connection to its `(sent, received)` bytes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransferSnapshot {
+    pub sent: u64,
+    pub received: u64,
+    pub connections: BTreeMap<u64, (u64, u64)>,
+}
+
+impl ConnectionStats {
+    /// Bytes sent to the pod.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Bytes received from the pod.
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+}
+
+impl TransferStats {
+    /// Bytes sent to the pod.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Bytes received from the pod.
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    pub fn add_sent(&self, bytes: u64) {
+        self.sent.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_received(&self, bytes: u64) {
+        self.received.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Registers a new connection, listed until `close_connection`. Its bytes
+    /// are counted by `add_connection_sent`/`add_connection_received`, which
+    /// add to the totals as well.
+    pub fn open_connection(&self) -> (u64, Arc<ConnectionStats>) {
+        let id = self.next_connection.fetch_add(1, Ordering::Relaxed) + 1;
+        let connection = Arc::new(ConnectionStats::default());
+        self.connections.lock().unwrap().insert(id, connection.clone());
+        (id, connection)
+    }
+
+    pub fn close_connection(&self, id: u64) {
+        self.connections.lock().unwrap().remove(&id);
+    }
+
+    pub fn add_connection_sent(&self, connection: &ConnectionStats, bytes: u64) {
+        connection.sent.fetch_add(bytes, Ordering::Relaxed);
+        self.add_sent(bytes);
+    }
+
+    pub fn add_connection_received(&self, connection: &ConnectionStats, bytes: u64) {
+        connection.received.fetch_add(bytes, Ordering::Relaxed);
+        self.add_received(bytes);
+    }
+
+    pub fn snapshot(&self) -> TransferSnapshot {
+        let connections = self
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&id, connection)| (id, (connection.sent(), connection.received())))
+            .collect();
+        TransferSnapshot {
+            sent: self.sent(),
+            received: self.received(),
+            connections,
+        }
+    }
 }
 
 pub struct PortForwardHandle {
     pub local_port: u16,
+    stats: Arc<TransferStats>,
     _handle: JoinHandle<()>,
 }
 
 impl PortForwardHandle {
-    pub fn new(local_port: u16, handle: JoinHandle<()>) -> Self {
+    pub fn new(local_port: u16, stats: Arc<TransferStats>, handle: JoinHandle<()>) -> Self {
         Self {
             local_port,
+            stats,
             _handle: handle,
         }
     }
+
+    pub fn stats(&self) -> &TransferStats {
+        &self.stats
+    }
 }
 
 #[async_trait]
@@ -50,5 +275,34 @@ pub trait K8sService: Clone + Send + Sync + 'static {
     async fn deploy_pod(&self) -> Result<PodRef, K8sError>;
     async fn wait_for_pod_ready(&self, pod_ref: &PodRef) -> Result<Pod, K8sError>;
     async fn port_forward(&self, pod_ref: &PodRef, local_port: u16) -> Result<PortForwardHandle, K8sError>;
-    async fn delete_pod(&self, pod_ref: &PodRef) -> Result<(), K8sError>;
+    /// Deletes the pod. `grace_period_seconds` overrides the pod's termination
+    /// grace period; `Some(0)` deletes it immediately.
+    async fn delete_pod(&self, pod_ref: &PodRef, grace_period_seconds: Option<u32>) -> Result<(), K8sError>;
+    /// Looks the pod up again, with its current phase. `None` if it is gone.
+    async fn get_pod(&self, pod_ref: &PodRef) -> Result<Option<PodRef>, K8sError>;
+    /// The node the pod is scheduled on, or the node's IP when its name is not
+    /// set. `None` while the pod is not scheduled yet.
+    async fn get_pod_node(&self, pod_ref: &PodRef) -> Result<Option<String>, K8sError>;
+    /// Waits up to `timeout` for the pod to be gone after `delete_pod`, which
+    /// only starts the deletion.
+    async fn wait_for_pod_deleted(&self, pod_ref: &PodRef, timeout: std::time::Duration) -> Result<(), K8sError>;
+    /// Lists k8socks pods in the configured namespace, or in every namespace
+    /// the user can see when `all_namespaces` is set.
+    async fn list_pods(&self, all_namespaces: bool) -> Result<Vec<PodRef>, K8sError>;
+    /// Runs `command` in the pod's sshd container with the local stdin and
+    /// stdout attached, allocating a TTY when `tty` is set.
+    async fn exec(&self, pod_ref: &PodRef, command: Vec<String>, tty: bool) -> Result<(), K8sError>;
+    /// Lists the events recorded for the pod, e.g. scheduling failures.
+    async fn get_pod_events(&self, pod_ref: &PodRef) -> Result<Vec<Event>, K8sError>;
+    /// Fetches the output of the pod's sshd container, only that of the last
+    /// `since` and only the last `tail` lines when given.
+    async fn pod_logs(
+        &self,
+        pod_ref: &PodRef,
+        since: Option<std::time::Duration>,
+        tail: Option<u64>,
+    ) -> Result<String, K8sError>;
+    /// Compares the pod's resource requests with the resource quotas of the
+    /// configured namespace and reports what would not fit.
+    async fn check_quota(&self) -> Result<Vec<QuotaProblem>, K8sError>;
 }
\ No newline at end of file
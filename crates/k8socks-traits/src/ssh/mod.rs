@@ -1,12 +1,18 @@
 use async_trait::async_trait;
 use thiserror::Error;
 use tokio::process::Child;
+use tokio::sync::oneshot;
 use crate::config::Config;
 
 #[derive(Error, Debug)]
 pub enum SshError {
     #[error("Failed to start SSH process: {0}")]
     ProcessError(#[from] std::io::Error),
+    #[error(
+        "SSH client '{0}' was not found. Install OpenSSH (e.g. `apt install openssh-client` or `brew install openssh`) \
+         or set `ssh_binary_path` / `--ssh-binary-path` to the location of your ssh executable"
+    )]
+    SshBinaryNotFound(String),
     #[error("SSH process exited with a non-zero status")]
     UnexpectedExit,
 }
@@ -14,6 +20,9 @@ pub enum SshError {
 /// A handle to a running SSH client subprocess.
 pub struct SshProcessHandle {
     pub child: Child,
+    /// The local port the SOCKS proxy listens on. Differs from the configured
+    /// `local_socks_port` when that was `0` and a free port was picked.
+    pub socks_port: u16,
 }
 
 /// The `SshService` trait defines the contract for managing the local SSH SOCKS proxy.
@@ -21,5 +30,8 @@ pub struct SshProcessHandle {
 pub trait SshService {
     fn new(config: &Config) -> Self;
     async fn start_socks_proxy(&self, forwarded_ssh_port: u16) -> Result<SshProcessHandle, SshError>;
-    async fn watch(&self, handle: SshProcessHandle) -> Result<(), SshError>;
+    /// Waits for the SSH process to exit, logging its output. Sending on
+    /// `stop` asks it to exit, and waits until it has; that counts as a clean
+    /// exit. A `stop` whose sender is dropped never fires.
+    async fn watch(&self, handle: SshProcessHandle, stop: oneshot::Receiver<()>) -> Result<(), SshError>;
 }
\ No newline at end of file
@@ -0,0 +1,60 @@
+/// Parses the `KEY=VALUE` lines of a dotenv file. Blank lines and lines
+/// starting with `#` are skipped, an `export ` prefix is allowed and a value
+/// may be wrapped in single or double quotes.
+pub fn parse_dotenv(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected KEY=VALUE", index + 1))?;
+        let key = key.trim();
+        if key.is_empty()
+            || key.starts_with(|c: char| c.is_ascii_digit())
+            || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(format!("line {}: invalid variable name '{}'", index + 1, key));
+        }
+        vars.push((key.to_string(), unquote(value.trim()).to_string()));
+    }
+    Ok(vars)
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dotenv() {
+        let content = "# per-project settings\n\nKUBECONFIG=~/.kube/staging\nexport HTTPS_PROXY = \"http://proxy:3128\"\nEMPTY=\nGREETING='a # b'\n";
+        let vars = parse_dotenv(content).unwrap();
+        let expected = [
+            ("KUBECONFIG", "~/.kube/staging"),
+            ("HTTPS_PROXY", "http://proxy:3128"),
+            ("EMPTY", ""),
+            ("GREETING", "a # b"),
+        ];
+        assert_eq!(vars.len(), expected.len());
+        for ((key, value), (expected_key, expected_value)) in vars.iter().zip(expected) {
+            assert_eq!((key.as_str(), value.as_str()), (expected_key, expected_value));
+        }
+
+        assert_eq!(parse_dotenv("A=1\nnot a pair").unwrap_err(), "line 2: expected KEY=VALUE");
+        assert!(parse_dotenv("=value").is_err());
+        assert!(parse_dotenv("1KEY=value").is_err());
+        assert!(parse_dotenv("MY-KEY=value").is_err());
+    }
+}
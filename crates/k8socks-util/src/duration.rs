@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+/// Parses a human-friendly duration such as `90`, `45s`, `30m`, `2h` or
+/// `1h30m`. A bare number is taken as seconds.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("empty duration".to_string());
+    }
+    if let Ok(secs) = input.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(format!("invalid duration '{}': unknown unit '{}'", input, c)),
+        };
+        let value: u64 = digits
+            .parse()
+            .map_err(|_| format!("invalid duration '{}': expected a number before '{}'", input, c))?;
+        total = value
+            .checked_mul(unit)
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or_else(|| format!("duration '{}' is too long", input))?;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        return Err(format!("invalid duration '{}': missing unit after '{}'", input, digits));
+    }
+    Ok(Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+
+        for invalid in ["", "m", "10x", "1h30", "-5m", "1.5h"] {
+            assert!(parse_duration(invalid).is_err(), "{:?} should be invalid", invalid);
+        }
+    }
+}
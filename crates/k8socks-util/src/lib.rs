@@ -0,0 +1,6 @@
+pub mod dotenv;
+pub mod duration;
+pub mod rate_limit;
+pub mod retry;
+pub mod session;
+pub mod template;
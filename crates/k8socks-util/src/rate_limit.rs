@@ -0,0 +1,127 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A token bucket: up to `burst` calls pass at once, after which calls are
+/// spaced out to `qps` per second. Callers queue in the order they arrive.
+#[derive(Debug)]
+pub struct RateLimiter {
+    qps: f64,
+    burst: f64,
+    state: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Negative while callers are waiting for tokens they have reserved.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    /// A limiter that starts with a full bucket. `qps` must be positive;
+    /// `burst` is at least 1.
+    pub fn new(qps: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            qps,
+            burst,
+            state: Mutex::new(Bucket {
+                tokens: burst,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// How many calls may pass at once.
+    pub fn burst(&self) -> u32 {
+        self.burst as u32
+    }
+
+    /// Waits until the caller may make one call.
+    pub async fn acquire(&self) {
+        self.acquire_many(1).await
+    }
+
+    /// Waits until the caller may make `n` calls at once, e.g. send `n` bytes
+    /// with a limiter that counts bytes. `n` may exceed the burst; the caller
+    /// then waits until the bucket has refilled the difference.
+    pub async fn acquire_many(&self, n: u32) {
+        let wait = {
+            let mut bucket = self.state.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * self.qps;
+            bucket.tokens = (bucket.tokens + refill).min(self.burst) - f64::from(n);
+            bucket.refilled_at = now;
+            if bucket.tokens >= 0.0 {
+                Duration::ZERO
+            } else {
+                Duration::from_secs_f64(-bucket.tokens / self.qps)
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2.0, 3);
+        let start = Instant::now();
+
+        // The burst passes at once.
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // Then one call every 500ms.
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_millis(1000));
+
+        // An idle limiter refills, but never beyond the burst.
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        let idle = Instant::now();
+        for _ in 0..4 {
+            limiter.acquire().await;
+        }
+        assert_eq!(idle.elapsed(), Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limiter_acquire_many() {
+        let limiter = RateLimiter::new(1000.0, 1000);
+        let start = Instant::now();
+        limiter.acquire_many(600).await;
+        limiter.acquire_many(400).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // More than the burst waits for the shortfall.
+        limiter.acquire_many(1500).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(1500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limiter_concurrent_callers() {
+        let limiter = std::sync::Arc::new(RateLimiter::new(10.0, 1));
+        let start = Instant::now();
+        let tasks: Vec<_> = (0..5)
+            .map(|_| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move { limiter.acquire().await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        // One at once, then four more 100ms apart.
+        assert_eq!(start.elapsed(), Duration::from_millis(400));
+    }
+}
@@ -0,0 +1,212 @@
+use std::future::Future;
+use std::time::Duration;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// How often and how patiently an operation is retried.
+///
+/// The delay before retry `n` (1-based) is `base_delay * 2^(n-1)`, capped at
+/// `max_delay`. `jitter` is the fraction (0.0 to 1.0) of that delay that may be
+/// randomly shaved off, so that many clients retrying at once spread out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackoffPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: f64,
+}
+
+impl BackoffPolicy {
+    /// A policy making `attempts` attempts in total, `interval` apart.
+    pub fn fixed(attempts: u32, interval: Duration) -> Self {
+        Self {
+            max_retries: attempts.saturating_sub(1),
+            base_delay: interval,
+            max_delay: interval,
+            jitter: 0.0,
+        }
+    }
+
+    /// The delay before the given retry (1-based).
+    pub fn delay_for<R: Rng>(&self, retry: u32, rng: &mut R) -> Duration {
+        let factor = 2u32.checked_pow(retry.saturating_sub(1)).unwrap_or(u32::MAX);
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter > 0.0 {
+            delay.mul_f64(1.0 - jitter * rng.r#gen::<f64>())
+        } else {
+            delay
+        }
+    }
+}
+
+/// Runs `operation` until it succeeds or the policy's retries are used up,
+/// returning the last error in that case. The operation is passed the 1-based
+/// number of the attempt, which is handy for logging.
+pub async fn retry_with_backoff<F, Fut, T, E>(policy: &BackoffPolicy, operation: F) -> Result<T, E>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    retry_with_backoff_rng(policy, &mut StdRng::from_entropy(), |_| true, operation).await
+}
+
+/// Like [`retry_with_backoff`], but only retries errors for which
+/// `should_retry` holds; any other error is returned right away.
+pub async fn retry_with_backoff_if<F, Fut, T, E, P>(
+    policy: &BackoffPolicy,
+    should_retry: P,
+    operation: F,
+) -> Result<T, E>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    P: Fn(&E) -> bool,
+{
+    retry_with_backoff_rng(policy, &mut StdRng::from_entropy(), should_retry, operation).await
+}
+
+/// Like [`retry_with_backoff_if`], drawing the jitter from the given RNG.
+pub async fn retry_with_backoff_rng<F, Fut, T, E, P, R>(
+    policy: &BackoffPolicy,
+    rng: &mut R,
+    should_retry: P,
+    mut operation: F,
+) -> Result<T, E>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    P: Fn(&E) -> bool,
+    R: Rng,
+{
+    let mut attempt = 1;
+    loop {
+        match operation(attempt).await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt > policy.max_retries || !should_retry(&e) => return Err(e),
+            Err(_) => tokio::time::sleep(policy.delay_for(attempt, rng)).await,
+        }
+        attempt += 1;
+    }
+}
+
+/// A random delay between zero and `max`, so that many clients doing the same
+/// thing at the same moment spread out.
+pub fn random_delay(max: Duration) -> Duration {
+    random_delay_rng(max, &mut StdRng::from_entropy())
+}
+
+/// Like [`random_delay`], drawing from the given RNG.
+pub fn random_delay_rng<R: Rng>(max: Duration, rng: &mut R) -> Duration {
+    max.mul_f64(rng.r#gen::<f64>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(max_retries: u32, jitter: f64) -> BackoffPolicy {
+        BackoffPolicy {
+            max_retries,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(4),
+            jitter,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_counts_attempts() {
+        let mut calls = 0;
+        let result: Result<(), u32> = retry_with_backoff(&policy(3, 0.0), |attempt| {
+            calls += 1;
+            async move { Err(attempt) }
+        })
+        .await;
+        // One initial attempt plus three retries, returning the last error.
+        assert_eq!(calls, 4);
+        assert_eq!(result, Err(4));
+
+        let mut calls = 0;
+        let result: Result<u32, ()> = retry_with_backoff(&policy(3, 0.0), |attempt| {
+            calls += 1;
+            async move { if attempt == 2 { Ok(attempt) } else { Err(()) } }
+        })
+        .await;
+        assert_eq!(calls, 2);
+        assert_eq!(result, Ok(2));
+    }
+
+    #[tokio::test]
+    async fn test_stops_at_permanent_errors() {
+        let mut calls = 0;
+        let result: Result<(), &str> = retry_with_backoff_if(
+            &policy(3, 0.0),
+            |e| *e == "transient",
+            |attempt| {
+                calls += 1;
+                async move { Err(if attempt < 2 { "transient" } else { "permanent" }) }
+            },
+        )
+        .await;
+        assert_eq!(calls, 2);
+        assert_eq!(result, Err("permanent"));
+    }
+
+    #[test]
+    fn test_delay_is_exponential_and_capped() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let policy = policy(10, 0.0);
+        let delays: Vec<u64> = (1..=5)
+            .map(|retry| policy.delay_for(retry, &mut rng).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 4, 4]);
+
+        // Retry counts far beyond the cap must not overflow.
+        assert_eq!(policy.delay_for(u32::MAX, &mut rng), Duration::from_millis(4));
+    }
+
+    #[test]
+    fn test_fixed_policy() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let policy = BackoffPolicy::fixed(3, Duration::from_millis(200));
+        assert_eq!(policy.max_retries, 2);
+        assert_eq!(policy.delay_for(1, &mut rng), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(2, &mut rng), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_jitter_is_deterministic_under_a_seed() {
+        let policy = BackoffPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.5,
+        };
+        let sample = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (1..=4).map(|retry| policy.delay_for(retry, &mut rng)).collect::<Vec<_>>()
+        };
+        assert_eq!(sample(42), sample(42));
+
+        for (retry, delay) in (1..=4).zip(sample(7)) {
+            let full = Duration::from_millis(100 * 2u64.pow(retry - 1));
+            assert!(delay <= full && delay >= full / 2, "{:?} outside jitter range", delay);
+        }
+    }
+
+    #[test]
+    fn test_random_delay() {
+        let max = Duration::from_millis(500);
+        let sample = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..8).map(|_| random_delay_rng(max, &mut rng)).collect::<Vec<_>>()
+        };
+        assert_eq!(sample(42), sample(42));
+        assert!(sample(7).iter().all(|delay| *delay <= max));
+        assert_eq!(random_delay(Duration::ZERO), Duration::ZERO);
+    }
+}
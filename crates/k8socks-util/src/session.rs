@@ -0,0 +1,19 @@
+use rand::Rng;
+
+/// Generates a short random id that tells the log lines of one session apart
+/// from those of another, e.g. `3f9a1c2e`.
+pub fn generate_session_id() -> String {
+    format!("{:08x}", rand::thread_rng().r#gen::<u32>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_session_id() {
+        let id = generate_session_id();
+        assert_eq!(id.len(), 8);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+}
@@ -0,0 +1,94 @@
+/// The placeholders of label and annotation values, filled in when the pod is
+/// deployed.
+pub const PLACEHOLDERS: &[&str] = &["user", "hostname", "date", "pod_name"];
+
+/// A `{name}` is a placeholder when the name is made of lowercase letters and
+/// `_`; any other brace, e.g. of a JSON value, is kept as it is.
+fn is_placeholder_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+}
+
+/// Splits `template` into its literal text and `{name}` placeholders, in order.
+fn parse(template: &str) -> Vec<Result<&str, &str>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    let mut literal_start = 0;
+    let mut offset = 0;
+    while let Some(open) = rest.find('{') {
+        let after = &rest[open + 1..];
+        match after.find('}') {
+            Some(close) if is_placeholder_name(&after[..close]) => {
+                parts.push(Ok(&template[literal_start..offset + open]));
+                parts.push(Err(&after[..close]));
+                offset += open + close + 2;
+                literal_start = offset;
+                rest = &after[close + 1..];
+            }
+            _ => {
+                offset += open + 1;
+                rest = after;
+            }
+        }
+    }
+    parts.push(Ok(&template[literal_start..]));
+    parts
+}
+
+/// The names of the placeholders in `template`, in order.
+pub fn placeholders(template: &str) -> Vec<&str> {
+    parse(template).into_iter().filter_map(Result::err).collect()
+}
+
+/// Replaces each `{name}` in `template` with `value(name)`. A placeholder
+/// without a value is an error, so that a typo such as `{usr}` does not end up
+/// on the pod.
+pub fn render(template: &str, mut value: impl FnMut(&str) -> Option<String>) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.len());
+    for part in parse(template) {
+        match part {
+            Ok(literal) => rendered.push_str(literal),
+            Err(name) => {
+                let value = value(name).ok_or_else(|| {
+                    format!("has an unknown placeholder '{{{}}}'; known are {{{}}}", name, PLACEHOLDERS.join("}, {"))
+                })?;
+                rendered.push_str(&value);
+            }
+        }
+    }
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(name: &str) -> Option<String> {
+        match name {
+            "user" => Some("alice".to_string()),
+            "pod_name" => Some("k8socks-abc123".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(render("{user}", sample), Ok("alice".to_string()));
+        assert_eq!(render("team-{user}-{pod_name}", sample), Ok("team-alice-k8socks-abc123".to_string()));
+        assert_eq!(render("no placeholders", sample), Ok("no placeholders".to_string()));
+        assert_eq!(render("", sample), Ok(String::new()));
+        // Braces around anything but a placeholder name stay as they are.
+        for literal in [r#"{"owner": "x"}"#, "{}", "{User}", "a { b", "} {"] {
+            assert_eq!(render(literal, sample), Ok(literal.to_string()));
+        }
+        assert_eq!(render(r#"{"by": "{user}"}"#, sample), Ok(r#"{"by": "alice"}"#.to_string()));
+
+        let err = render("{usr}", sample).unwrap_err();
+        assert!(err.contains("'{usr}'") && err.contains("{user}, {hostname}"), "{}", err);
+    }
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(placeholders("{user}@{hostname} on {date}"), ["user", "hostname", "date"]);
+        assert!(placeholders(r#"{"key": 1}"#).is_empty());
+    }
+}